@@ -17,29 +17,33 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>
 */
 
 use clap::{Arg, ArgAction, Command};
+use clap_complete::{generate, Shell};
 use colored::*;
 use rusqlite::{Connection, OpenFlags, Result};
 use rust_stemmers::{Algorithm, Stemmer};
 use snip::analysis::SnipAnalysis;
-use snip::doc::Snip;
+use snip::doc::{IndexQueue, Snip};
 use snip::error::SnipError;
 use snip::search::{SearchMethod, SearchQuery};
 use std::collections::HashMap;
 use std::env;
 use std::error::Error;
 use std::io::{IsTerminal, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use unicode_segmentation::UnicodeSegmentation;
 use uuid::Uuid;
 
-fn main() -> Result<(), Box<dyn Error>> {
+/// Builds the full `snip` command tree, shared by argument parsing in `main` and by
+/// `clap_complete`'s shell-script generation so completions can never drift out of sync
+/// with the subcommands/flags actually defined here.
+fn build_cli() -> Command {
     let version_info = format!(
         "{} ({})\n{}",
         env!("CARGO_PKG_VERSION"),
         env!("GIT_HASH"),
         env!("CARGO_PKG_AUTHORS")
     );
-    let cmd = Command::new("snip")
+    Command::new("snip")
         .bin_name("snip")
         .author(env!("CARGO_PKG_AUTHORS"))
         .long_version(&version_info)
@@ -82,12 +86,48 @@ fn main() -> Result<(), Box<dyn Error>> {
                         .short('v')
                         .num_args(0)
                         .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("lossy")
+                        .help("decode invalid UTF-8 as U+FFFD instead of failing")
+                        .long("lossy")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue),
                 ),
         )
         .subcommand(
             Command::new("completion")
                 .arg_required_else_help(true)
-                .arg(Arg::new("shell").help("shell name")),
+                .arg(
+                    Arg::new("shell")
+                        .help("shell to generate completions for")
+                        .value_parser(["bash", "zsh", "fish", "powershell", "elvish"]),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Export documents, with attachments, to a verifiable manifest archive")
+                .arg(
+                    Arg::new("ids")
+                        .help("partial/full uuids of documents to export (reads uuids, one per line, from stdin if omitted)")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("all")
+                        .help("export all documents")
+                        .short('a')
+                        .long("all")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("output")
+                        .help("destination directory")
+                        .short('o')
+                        .long("output")
+                        .num_args(1)
+                        .required(true),
+                ),
         )
         .subcommand(
             Command::new("rename")
@@ -309,6 +349,43 @@ fn main() -> Result<(), Box<dyn Error>> {
                         .required(false)
                         .value_delimiter(','),
                 )
+                .arg(
+                    Arg::new("typo")
+                        .help("tolerate up to N edits (typos) per search term, or \"auto\" to pick N per term from its length")
+                        .long("typo")
+                        .num_args(1)
+                        .required(false)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("prefix")
+                        .help("treat the last search term as a still-being-typed prefix (search-as-you-type); combine with --typo to tolerate edits in the prefix")
+                        .long("prefix")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("boost")
+                        .help("optional term that boosts rank when present, without narrowing which documents match (repeatable)")
+                        .long("boost")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("bool")
+                        .help("interpret the search terms as a single boolean query expression (parentheses, AND/OR/NOT, \"phrases\") instead of a flat include/exclude list")
+                        .long("bool")
+                        .num_args(0)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("sort")
+                        .help("order matched documents by relevance (typo count, term proximity, then frequency), TF-IDF + proximity score, modification time, or name")
+                        .long("sort")
+                        .num_args(1)
+                        .required(false)
+                        .value_parser(["relevance", "score", "time", "name"])
+                        .action(ArgAction::Append),
+                )
                 .arg(Arg::new("terms").action(ArgAction::Append).required(true)),
         )
         .subcommand(
@@ -334,6 +411,10 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .arg_required_else_help(false)
                 .arg(Arg::new("words")),
         )
+        .subcommand(
+            Command::new("verify")
+                .about("Re-hash stored attachments and report any that no longer match"),
+        )
         .subcommand(
             Command::new("update")
                 .about("Update document from modified file")
@@ -352,11 +433,12 @@ fn main() -> Result<(), Box<dyn Error>> {
                         .num_args(0)
                         .action(ArgAction::SetTrue),
                 ),
-        );
+        )
+}
 
-    // completion script embeds
-    let completion_bash = include_bytes!("../completions/bash");
-    let matches = cmd.get_matches();
+fn main() -> Result<(), Box<dyn Error>> {
+    let cmd = build_cli();
+    let matches = cmd.clone().get_matches();
     let db_file_default = ".snip.sqlite3".to_string();
     let home_dir = match env::var("HOME") {
         Ok(v) => v,
@@ -366,21 +448,37 @@ fn main() -> Result<(), Box<dyn Error>> {
         Some(v) => v.to_owned(),
         None => env::var("SNIP_DB").unwrap_or(format!("{}/{}", home_dir, db_file_default)),
     };
+    let db_path_buf = PathBuf::from(&db_path);
+    let read_only = matches.get_flag("read-only");
 
-    let conn = match matches.get_flag("read-only") {
+    let conn = match read_only {
         true => Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?,
         false => Connection::open(db_path)?,
     };
     // ensure that tables are present for basic functionality
     snip::doc::create_snip_tables(&conn)?;
 
+    // writes below enqueue their indexing work onto a background worker instead of
+    // reindexing inline, so a bulk import isn't blocked re-tokenizing after every
+    // document; `flush()` at the end of `main` waits for the queue to drain before
+    // exiting, so CLI output still only appears once the index is current. Read-only
+    // invocations never write, so no queue is spawned.
+    let index_queue = if read_only {
+        None
+    } else {
+        Some(IndexQueue::spawn(db_path_buf))
+    };
+
     // process all subcommands as in: https://docs.rs/clap/latest/clap/_derive/_cookbook/git/index.html
     // ADD
     if let Some(("add", sub_matches)) = matches.subcommand() {
         // document text
+        let lossy = sub_matches.get_flag("lossy");
         let mut text: String = String::new();
         match sub_matches.get_one::<String>("file") {
+            Some(v) if lossy => text = snip::doc::read_file_lossy(v)?,
             Some(v) => text = std::fs::read_to_string(v)?,
+            None if lossy => text = snip::doc::read_stdin_lossy()?,
             None => {
                 std::io::stdin().read_to_string(&mut text)?; // FIXME I don't like this
             }
@@ -393,7 +491,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         };
 
         // create document
-        let mut s = Snip {
+        let s = Snip {
             uuid: Uuid::new_v4(),
             name: name.to_owned(),
             timestamp: chrono::Utc::now(),
@@ -402,8 +500,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             attachments: Vec::new(),
         };
 
-        s.insert(&conn)?;
-        s.index(&conn)?;
+        snip::doc::insert_snip(&conn, &s, index_queue.as_ref())?;
         if sub_matches.get_flag("verbose") {
             print!("{}", s.text);
         }
@@ -429,8 +526,13 @@ fn main() -> Result<(), Box<dyn Error>> {
                 // add each file
                 for f in files {
                     let path = Path::new(f);
-                    snip::attachment::add_attachment(&conn, snip_uuid, path)?;
-                    println!("  added {}", f);
+                    let status = snip::attachment::add_attachment(&conn, snip_uuid, path)?;
+                    match status {
+                        snip::attachment::AttachmentDedupStatus::Stored => println!("  added {}", f),
+                        snip::attachment::AttachmentDedupStatus::Deduplicated => {
+                            println!("  added {} (deduplicated, identical content already stored)", f)
+                        }
+                    }
                 }
             } else {
                 eprintln!("no files specified");
@@ -537,9 +639,47 @@ fn main() -> Result<(), Box<dyn Error>> {
         let shell_name = sub_matches
             .get_one::<String>("shell")
             .ok_or("shell name not provided")?;
-        if shell_name == "bash" {
-            println!("{}", std::str::from_utf8(completion_bash)?);
+        let shell: Shell = shell_name
+            .parse()
+            .map_err(|_| format!("unsupported shell: {}", shell_name))?;
+        let mut cmd = cmd;
+        let bin_name = cmd.get_name().to_string();
+        generate(shell, &mut cmd, bin_name, &mut std::io::stdout());
+    }
+
+    // EXPORT
+    if let Some(("export", sub_matches)) = matches.subcommand() {
+        let output = sub_matches
+            .get_one::<String>("output")
+            .ok_or("output directory not provided")?;
+        let dest_dir = Path::new(output);
+
+        let uuids: Vec<Uuid> = if sub_matches.get_flag("all") {
+            snip::doc::uuid_list(&conn, 0)?
+        } else if let Some(ids) = sub_matches.get_many::<String>("ids") {
+            let mut uuids = Vec::new();
+            for id_str in ids {
+                uuids.push(snip::search::search_uuid(&conn, id_str)?);
+            }
+            uuids
+        } else {
+            // reads a search result set piped in as one partial/full uuid per line
+            let mut uuids = Vec::new();
+            for line in snip::doc::read_lines_from_stdin()?.lines() {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                uuids.push(snip::search::search_uuid(&conn, line)?);
+            }
+            uuids
+        };
+
+        let entries = snip::doc::export_snips(&conn, &uuids, dest_dir)?;
+        for entry in &entries {
+            println!("exported {} {}", entry.uuid, entry.name);
         }
+        eprintln!("exported {} document(s) to {:?}", entries.len(), dest_dir);
     }
 
     // GET
@@ -595,7 +735,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             for file in files.into_iter() {
                 print!("importing {:?}...", file);
 
-                let mut s = snip::doc::from_file(file)?;
+                let s = snip::doc::from_file(file)?;
                 if snip::doc::get_from_uuid(&conn, &s.uuid).is_ok() {
                     println!("refusing duplicate insert {}", s.uuid);
                     errors = true;
@@ -604,9 +744,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                 }
 
                 // check for existing id to avoid duplicates
-                s.insert(&conn)?;
-                // always index after import
-                s.index(&conn)?;
+                snip::doc::insert_snip(&conn, &s, index_queue.as_ref())?;
                 println!("success");
             }
             if errors {
@@ -707,7 +845,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         s.name = name;
 
         // write changes
-        s.update(&conn)?;
+        s.update(&conn, index_queue.as_ref())?;
     }
 
     // RM
@@ -719,7 +857,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                 // obtain full id
                 let id = snip::search::search_uuid(&conn, id_str)?;
                 let s = snip::doc::get_from_uuid(&conn, &id)?;
-                snip::doc::remove_snip(&conn, id)?;
+                snip::doc::remove_snip(&conn, id, index_queue.as_ref())?;
                 println!("{}/{} removed {} {}", i + 1, ids_str.len(), id, s.name);
             }
         }
@@ -760,6 +898,13 @@ fn main() -> Result<(), Box<dyn Error>> {
                 terms_exclude = stem_vec(args.map(|x| x.to_owned()).collect());
             }
 
+            // optional "should" terms: never narrow the candidate set, but boost rank
+            // and appear in the excerpt/summary output when a document does contain them
+            let mut terms_optional: Vec<String> = Vec::new();
+            if let Some(args) = sub_matches.get_many::<String>("boost") {
+                terms_optional = stem_vec(args.map(|x| x.to_owned()).collect());
+            }
+
             // establish document limit
             let mut limit: Option<usize> = None;
             if let Some(document_limit) = sub_matches.get_one::<String>("limit") {
@@ -783,18 +928,57 @@ fn main() -> Result<(), Box<dyn Error>> {
                 context_raw = *raw;
             }
 
+            // tolerate typos in search terms, within the given edit distance, if requested;
+            // "auto" derives each term's own budget from its length instead of a fixed N
+            let typo_auto = sub_matches.get_one::<String>("typo").map(String::as_str) == Some("auto");
+            let typo_distance = match sub_matches.get_one::<String>("typo") {
+                Some(_) if typo_auto => None,
+                Some(v) => Some(v.parse::<usize>()?),
+                None => None,
+            };
+            let method = if typo_auto {
+                SearchMethod::IndexFuzzyAuto
+            } else {
+                match typo_distance {
+                    Some(d) => SearchMethod::IndexFuzzy(d),
+                    None => SearchMethod::IndexStem,
+                }
+            };
+            // treat the last search term as a prefix (autocomplete), tolerant of the
+            // fixed edit distance --typo N selected (0 if --typo is not given or is "auto")
+            let prefix_distance = if sub_matches.get_flag("prefix") {
+                Some(typo_distance.unwrap_or(0))
+            } else {
+                None
+            };
+
             // perform search and print summary
             let search_query = SearchQuery {
                 terms_include: terms_include.clone(),
                 terms_exclude: terms_exclude.clone(),
-                terms_optional: vec![],
-                method: SearchMethod::IndexStem,
+                terms_optional: terms_optional.clone(),
+                method,
                 uuids,
+                prefix_distance,
                 limit,
             };
-            let search_results = match snip::search::search_structured(&conn, search_query) {
-                Ok(v) => v,
-                Err(e) => return Err(Box::new(e)),
+            // --bool treats the whole "terms" list as one boolean query expression
+            // (parentheses, AND/OR/NOT, "phrases") walked via the Operation tree,
+            // rather than a flat include/exclude/optional list
+            let using_bool_query = sub_matches.get_flag("bool");
+            let raw_bool_query = terms.join(" ");
+            let bool_op = snip::search::parse_query(&raw_bool_query);
+
+            let mut search_results = if using_bool_query {
+                match snip::search::search_boolean_query(&conn, &raw_bool_query) {
+                    Ok(v) => v,
+                    Err(e) => return Err(Box::new(e)),
+                }
+            } else {
+                match snip::search::search_structured(&conn, search_query.clone()) {
+                    Ok(v) => v,
+                    Err(e) => return Err(Box::new(e)),
+                }
             };
 
             // exit if no results are present
@@ -802,6 +986,55 @@ fn main() -> Result<(), Box<dyn Error>> {
                 return Ok(());
             }
 
+            // order matched documents per --sort (relevance by default)
+            match sub_matches
+                .get_one::<String>("sort")
+                .map(String::as_str)
+                .unwrap_or("relevance")
+            {
+                "time" => {
+                    let mut dated: Vec<(Option<chrono::DateTime<chrono::FixedOffset>>, _)> =
+                        search_results
+                            .items
+                            .into_iter()
+                            .map(|item| {
+                                let timestamp = snip::doc::get_from_uuid(&conn, &item.uuid)
+                                    .ok()
+                                    .map(|s| s.timestamp);
+                                (timestamp, item)
+                            })
+                            .collect();
+                    dated.sort_by(|a, b| b.0.cmp(&a.0));
+                    search_results.items = dated.into_iter().map(|(_, item)| item).collect();
+                }
+                "name" => {
+                    let mut named: Vec<(String, _)> = search_results
+                        .items
+                        .into_iter()
+                        .map(|item| {
+                            let name = snip::doc::get_from_uuid(&conn, &item.uuid)
+                                .ok()
+                                .map(|s| s.name)
+                                .unwrap_or_default();
+                            (name, item)
+                        })
+                        .collect();
+                    named.sort_by(|a, b| a.0.cmp(&b.0));
+                    search_results.items = named.into_iter().map(|(_, item)| item).collect();
+                }
+                "score" => {
+                    // search_structured/search_boolean_query already sort items
+                    // descending by score_search_query's TF-IDF + proximity score
+                }
+                _ => {
+                    // a raw boolean query has no flat SearchQuery to rank typo
+                    // distance/proximity against, so it keeps its candidate-set order
+                    if !using_bool_query {
+                        snip::search::sort_by_relevance(&conn, &mut search_results, &search_query)?;
+                    }
+                }
+            }
+
             // print to stderr to keep redirection clean
             eprint!("document");
             if search_results.items.len() != 1 {
@@ -817,6 +1050,20 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
             eprintln!(" occurrences: {}", term_match_count);
 
+            // include optional "should" terms alongside terms_include in the displayed
+            // summary/excerpts, since a document's matches map may carry positions for
+            // either; under --bool, display every non-negated leaf term of the parsed
+            // query instead, since there is no flat terms_include/terms_optional
+            let terms_display: Vec<String> = if using_bool_query {
+                snip::search::positive_leaf_terms(&bool_op)
+            } else {
+                terms_include
+                    .iter()
+                    .chain(terms_optional.iter())
+                    .cloned()
+                    .collect()
+            };
+
             // we don't need excerpts for count only
             if !sub_matches.get_flag("count") {
                 for item in &search_results.items {
@@ -832,7 +1079,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                     }
                     print!(" [");
                     // use argument terms vector to order by term
-                    for (i, term) in terms_include.iter().enumerate() {
+                    for (i, term) in terms_display.iter().enumerate() {
                         if let Some(count) = terms_summary.get(term.as_str()) {
                             print!("{}: {}", term, count);
                             if i != terms_summary.len() - 1 {
@@ -844,7 +1091,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                     println!();
 
                     // for each position, gather context and display
-                    for term in &terms_include {
+                    for term in &terms_display {
                         if let Some(positions) = item.matches.get(term.as_str()) {
                             for (i, pos) in positions.iter().enumerate() {
                                 // if limit is hit, break immediately
@@ -926,14 +1173,29 @@ fn main() -> Result<(), Box<dyn Error>> {
         println!("{:?}", stems);
     }
 
+    // VERIFY
+    if let Some(("verify", _)) = matches.subcommand() {
+        let mismatches = snip::attachment::verify_attachments(&conn)?;
+        if mismatches.is_empty() {
+            println!("all attachments match their recorded hash");
+        } else {
+            for m in &mismatches {
+                println!(
+                    "MISMATCH {} {} expected {} got {}",
+                    m.uuid, m.name, m.expected_hash, m.actual_hash
+                );
+            }
+            eprintln!("{} attachment(s) failed verification", mismatches.len());
+            std::process::exit(1);
+        }
+    }
+
     // UPDATE
     if let Some(("update", sub_matches)) = matches.subcommand() {
         if let Some(file) = sub_matches.get_one::<String>("file") {
             let s = snip::doc::from_file(file)?;
-            s.update(&conn)?;
+            s.update(&conn, index_queue.as_ref())?;
             let mut s = snip::doc::get_from_uuid(&conn, &s.uuid)?;
-            // re-index due to changed content
-            s.index(&conn)?;
             eprintln!("update successful");
 
             // collect attachments before printing so they are included in output
@@ -953,6 +1215,13 @@ fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
+    // wait for any queued indexing work to finish before returning, so output above
+    // is only ever stale with respect to an index that is still in progress, never one
+    // that silently never ran
+    if let Some(queue) = &index_queue {
+        queue.flush()?;
+    }
+
     Ok(())
 }
 
@@ -1,5 +1,6 @@
 pub mod analysis;
 pub mod attachment;
+pub mod bitmap;
 pub mod doc;
 pub mod error;
 pub mod search;
@@ -7,6 +8,7 @@ pub mod test_prep;
 
 pub use analysis::*;
 pub use attachment::*;
+pub use bitmap::*;
 pub use doc::*;
 pub use error::SnipError;
 pub use search::*;
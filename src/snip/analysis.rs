@@ -1,7 +1,10 @@
 use colored::*;
+use std::collections::HashSet;
 use std::error::Error;
-use rusqlite::Connection;
+use std::sync::{OnceLock, RwLock};
+use rusqlite::{Connection, OptionalExtension};
 use unicode_segmentation::UnicodeSegmentation;
+use uuid::Uuid;
 
 /// Analysis of the document derived from
 #[derive(Debug)]
@@ -161,9 +164,106 @@ impl SnipAnalysis {
         }
         snip_words
     }
+
+    /// Like `get_excerpt`, but chooses its window to cover as many of `query_stems` as
+    /// possible instead of centering on a single position. Collects every word position
+    /// matching any query stem, then slides over that occurrence list to find the span
+    /// covering the most distinct stems, breaking ties by the smallest total proximity
+    /// cost (the sum of the gaps between consecutive hits in the span, i.e. the word
+    /// distance from its first hit to its last).
+    /// Every matching stem, not just one, is highlighted in the returned `Excerpt`.
+    pub fn get_best_excerpt(&self, query_stems: &[String]) -> Result<Excerpt, Box<dyn Error>> {
+        if query_stems.is_empty() {
+            return Err("no query stems given".into());
+        }
+
+        let mut occurrences: Vec<(usize, usize)> = Vec::new(); // (word position, stem index)
+        for (pos, word) in self.words.iter().enumerate() {
+            if let Some(stem_idx) = query_stems.iter().position(|s| *s == word.stem) {
+                occurrences.push((pos, stem_idx));
+            }
+        }
+        if occurrences.is_empty() {
+            return Err("none of the query terms appear in this document".into());
+        }
+        occurrences.sort_unstable();
+
+        // (distinct stems covered, proximity cost, span start, span end); larger distinct
+        // count wins, then smaller cost, so ties are broken toward tighter clustering
+        let mut best: Option<(usize, usize, usize, usize)> = None;
+        for end in 0..occurrences.len() {
+            let mut seen = HashSet::new();
+            let mut cost = 0;
+            let mut last_pos: Option<usize> = None;
+            for start in (0..=end).rev() {
+                let (pos, stem_idx) = occurrences[start];
+                seen.insert(stem_idx);
+                if let Some(lp) = last_pos {
+                    cost += lp - pos;
+                }
+                last_pos = Some(pos);
+
+                let distinct = seen.len();
+                let (span_start, span_end) = (occurrences[start].0, occurrences[end].0);
+                let better = match best {
+                    None => true,
+                    Some((best_distinct, best_cost, _, _)) => {
+                        distinct > best_distinct || (distinct == best_distinct && cost < best_cost)
+                    }
+                };
+                if better {
+                    best = Some((distinct, cost, span_start, span_end));
+                }
+            }
+        }
+        let (_, _, span_start, span_end) = best.expect("occurrences is non-empty");
+
+        let positions = self.get_term_context_span(span_start, span_end, 8);
+        let position_first = *positions.first().ok_or("finding first context position")?;
+        let position_last = *positions.last().ok_or("finding last context position")?;
+
+        let mut excerpt = Excerpt {
+            position_first,
+            position_last,
+            positions: Vec::new(),
+            terms: Vec::new(),
+        };
+
+        for p in &positions {
+            excerpt.positions.push(*p);
+            let snip_word = &self.words[*p];
+            let mut excerpt_term = ExcerptTerm {
+                stem: snip_word.stem.clone(),
+                term: snip_word.word.clone(),
+                highlight: query_stems.contains(&snip_word.stem),
+                range_prefix: (position_first, position_last),
+                suffix_clean: String::new(),
+            };
+
+            if let Some(suffix) = &snip_word.suffix {
+                let suffix_stripped =
+                    suffix.replace(['\n', '\r', char::from_u32(0x0au32).unwrap()], " ");
+                excerpt_term.suffix_clean = collapse_spaces(suffix_stripped);
+            }
+
+            excerpt.terms.push(excerpt_term);
+        }
+        Ok(excerpt)
+    }
+
+    /// Like `get_term_context_positions`, but anchors the `count`-word padding on each
+    /// side of an inclusive `[start, end]` span rather than a single position.
+    fn get_term_context_span(&self, start: usize, end: usize, count: usize) -> Vec<usize> {
+        let context_prefix_pos: usize = match start as i64 - count as i64 {
+            x if x <= 0 => 0,
+            x => x as usize,
+        };
+        let context_suffix_pos: usize = std::cmp::min(end + count + 1, self.words.len());
+        (context_prefix_pos..context_suffix_pos).collect()
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WordIndex {
     pub count: u64,
     pub positions: Vec<u64>,
@@ -185,6 +285,18 @@ impl WordIndex {
         }
         Ok(output)
     }
+
+    /// Combines this term's in-document `count` with its corpus-wide `document_frequency`
+    /// (out of `total_documents`) into a smoothed TF-IDF relevance weight: a term that
+    /// occurs often in this document but rarely elsewhere in the corpus weighs more than
+    /// one that occurs often everywhere. Adds 1 to both the numerator and denominator of
+    /// the idf term (and 1 to the whole log) so a term with full or near-zero document
+    /// frequency neither divides by zero nor collapses to zero weight.
+    pub fn tfidf_weight(&self, document_frequency: u64, total_documents: u64) -> f64 {
+        let tf = self.count as f64;
+        let idf = ((total_documents as f64 + 1.0) / (document_frequency as f64 + 1.0)).ln() + 1.0;
+        tf * idf
+    }
 }
 
 /// Collapse recurring space characters in a string
@@ -202,6 +314,108 @@ fn collapse_spaces(s: String) -> String {
     output
 }
 
+/// Common, low-information English words excluded from indexing and query evaluation by
+/// default. Stored as already-stemmed forms, since both `Snip::index` and query leaves
+/// compare against a word's stem rather than its surface form.
+fn default_stop_words() -> HashSet<String> {
+    [
+        "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "ha", "he", "in", "is",
+        "it", "it'", "of", "on", "that", "the", "to", "wa", "were", "will", "with",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+/// Normalizes a surface word into the stem it is indexed and searched under. The default
+/// implementation wraps a Snowball stemmer for a configurable language; a non-English
+/// corpus can call `set_analyzer` with its own implementation instead.
+pub trait Analyzer: Send + Sync {
+    fn stem(&self, word: &str) -> String;
+}
+
+/// Default analyzer: lowercases, normalizes the right single-quote some text uses in
+/// place of an ASCII apostrophe, then stems with `rust_stemmers`'s Snowball
+/// implementation for `language`.
+pub struct SnowballAnalyzer {
+    language: rust_stemmers::Algorithm,
+    stemmer: rust_stemmers::Stemmer,
+}
+
+impl SnowballAnalyzer {
+    pub fn new(language: rust_stemmers::Algorithm) -> Self {
+        SnowballAnalyzer {
+            language,
+            stemmer: rust_stemmers::Stemmer::create(language),
+        }
+    }
+
+    pub fn language(&self) -> rust_stemmers::Algorithm {
+        self.language
+    }
+}
+
+impl Analyzer for SnowballAnalyzer {
+    fn stem(&self, word: &str) -> String {
+        let word_tmp = word.to_lowercase().replace('\u{2019}', "'");
+        self.stemmer.stem(word_tmp.as_str()).to_string()
+    }
+}
+
+impl Default for SnowballAnalyzer {
+    fn default() -> Self {
+        SnowballAnalyzer::new(rust_stemmers::Algorithm::English)
+    }
+}
+
+fn analyzer() -> &'static RwLock<Box<dyn Analyzer>> {
+    static ANALYZER: OnceLock<RwLock<Box<dyn Analyzer>>> = OnceLock::new();
+    ANALYZER.get_or_init(|| RwLock::new(Box::new(SnowballAnalyzer::default())))
+}
+
+/// Replaces the active analyzer used by both `Snip::index`'s stemming pass and query-term
+/// stemming, letting a non-English corpus plug in its own stemmer (or any other word
+/// normalization) in place of the default Snowball/English pipeline.
+///
+/// Like `set_stop_words`, this does not retroactively touch documents already indexed
+/// under the previous analyzer; callers typically follow this with `index_all_items` to
+/// rebuild the index against the new one.
+pub fn set_analyzer(new_analyzer: Box<dyn Analyzer>) {
+    *analyzer().write().expect("analyzer lock poisoned") = new_analyzer;
+}
+
+/// Selects a Snowball stemming language from `rust_stemmers::Algorithm`, a convenience
+/// over calling `set_analyzer(Box::new(SnowballAnalyzer::new(language)))` directly.
+pub fn set_stemmer_language(language: rust_stemmers::Algorithm) {
+    set_analyzer(Box::new(SnowballAnalyzer::new(language)));
+}
+
+/// Stems `word` using the currently active analyzer.
+pub fn stem_word(word: &str) -> String {
+    analyzer().read().expect("analyzer lock poisoned").stem(word)
+}
+
+fn stop_words() -> &'static RwLock<HashSet<String>> {
+    static STOP_WORDS: OnceLock<RwLock<HashSet<String>>> = OnceLock::new();
+    STOP_WORDS.get_or_init(|| RwLock::new(default_stop_words()))
+}
+
+/// Replaces the active stop-word set used to filter terms out of `Snip::index` and query
+/// evaluation. `words` should already be stemmed, matching how terms are stored in
+/// `snip_index_rs`. Pass an empty set to disable stop-word filtering entirely.
+///
+/// Changing the active set does not retroactively touch documents already indexed under
+/// the old one; callers typically follow this with `index_all_items` to rebuild the
+/// index from scratch against the new set.
+pub fn set_stop_words(words: HashSet<String>) {
+    *stop_words().write().expect("stop word lock poisoned") = words;
+}
+
+/// Returns whether `stem` (already lowercased and stemmed) is in the active stop-word set.
+pub fn is_stop_word(stem: &str) -> bool {
+    stop_words().read().expect("stop word lock poisoned").contains(stem)
+}
+
 /// provide stats about the document and index
 pub fn stats_index(conn: &Connection) -> Result<AnalysisStats, Box<dyn Error>> {
     let mut stats = AnalysisStats {
@@ -247,6 +461,78 @@ pub fn stats_index(conn: &Connection) -> Result<AnalysisStats, Box<dyn Error>> {
     Ok(stats)
 }
 
+/// Like `stats_index`, but scoped to a single document: total indexed word count, terms
+/// and their in-document popularity, and the number of distinct terms, all restricted to
+/// `uuid`'s own `snip_index_rs` rows.
+pub fn stats_index_for(conn: &Connection, uuid: &Uuid) -> Result<AnalysisStats, Box<dyn Error>> {
+    let mut stats = AnalysisStats {
+        terms_with_counts: Vec::new(),
+        terms_total: 0,
+        terms_unique: 0,
+    };
+
+    let mut stmt = conn.prepare("SELECT COALESCE(SUM(count), 0) FROM snip_index_rs WHERE uuid = :uuid")?;
+    let total: i64 = stmt.query_row(&[(":uuid", &uuid.to_string())], |row| row.get(0))?;
+    stats.terms_total = total as u64;
+
+    let mut stmt = conn.prepare(
+        "SELECT term, SUM(count) FROM snip_index_rs WHERE uuid = :uuid GROUP BY term ORDER BY SUM(count) DESC",
+    )?;
+    let query_iter = stmt.query_and_then(&[(":uuid", &uuid.to_string())], |row| -> Result<(String, u64), Box<dyn Error>> {
+        let term: String = row.get(0)?;
+        let count: u64 = row.get(1)?;
+        Ok((term, count))
+    })?;
+    for row in query_iter.flatten() {
+        stats.terms_with_counts.push(row);
+    }
+
+    stats.terms_unique = stats.terms_with_counts.len() as u64;
+
+    Ok(stats)
+}
+
+/// Recomputes `term`'s document frequency and total occurrence count straight from
+/// `snip_index_rs` and writes it into `snip_term_summary`, so the summary stays in sync
+/// with the index in O(documents containing `term`) rather than a full-table rescan.
+/// Called whenever a document is indexed or removed, for every term it touched. Removes
+/// the summary row entirely once no document contains the term any longer, rather than
+/// leaving a stale zero-row behind.
+pub fn refresh_term_summary(conn: &Connection, term: &str) -> Result<(), Box<dyn Error>> {
+    let mut stmt = conn.prepare(
+        "SELECT COUNT(DISTINCT uuid), COALESCE(SUM(count), 0) FROM snip_index_rs WHERE term = :term",
+    )?;
+    let (document_frequency, total_count): (i64, i64) =
+        stmt.query_row(&[(":term", &term)], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+    if document_frequency == 0 {
+        conn.execute(
+            "DELETE FROM snip_term_summary WHERE term = :term",
+            &[(":term", &term)],
+        )?;
+        return Ok(());
+    }
+
+    conn.execute(
+        "INSERT INTO snip_term_summary(term, document_frequency, total_count) \
+         VALUES (:term, :df, :total)
+         ON CONFLICT(term) DO UPDATE SET document_frequency = :df, total_count = :total",
+        rusqlite::named_params! { ":term": term, ":df": document_frequency, ":total": total_count },
+    )?;
+    Ok(())
+}
+
+/// Reads a term's maintained corpus-wide document frequency out of `snip_term_summary`,
+/// or `0` if the term has never been indexed (or has since been fully removed).
+pub fn term_document_frequency(conn: &Connection, term: &str) -> Result<u64, Box<dyn Error>> {
+    let mut stmt =
+        conn.prepare("SELECT document_frequency FROM snip_term_summary WHERE term = :term")?;
+    let df: Option<i64> = stmt
+        .query_row(&[(":term", &term)], |row| row.get(0))
+        .optional()?;
+    Ok(df.unwrap_or(0) as u64)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -287,6 +573,30 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_get_best_excerpt_covers_multiple_query_stems() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        let id = Uuid::try_parse(ID_STR)?;
+        let mut s = snip::get_from_uuid(&conn, &id)?;
+        s.analyze()?;
+
+        let query_stems = vec!["lorem".to_string(), "ipsum".to_string()];
+        let excerpt = s.analysis.get_best_excerpt(&query_stems)?;
+
+        // every word in the chosen window whose stem matches a query term is highlighted
+        let highlighted: HashSet<String> = excerpt
+            .terms
+            .iter()
+            .filter(|t| t.highlight)
+            .map(|t| t.stem.clone())
+            .collect();
+        assert!(highlighted.contains("lorem"));
+        assert!(highlighted.contains("ipsum"));
+        assert!(excerpt.position_first <= excerpt.position_last);
+
+        Ok(())
+    }
+
     #[test]
     fn test_stats_index() -> Result<(), Box<dyn Error>> {
         let conn = prepare_database().expect("preparing in-memory database");
@@ -298,4 +608,78 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_stats_index_for_scopes_to_one_document() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+        let id = Uuid::try_parse(ID_STR)?;
+
+        let scoped = stats_index_for(&conn, &id)?;
+        let global = stats_index(&conn)?;
+        assert!(scoped.terms_total > 0);
+        assert!(scoped.terms_total <= global.terms_total);
+        assert!(scoped.terms_unique <= global.terms_unique);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_refresh_term_summary_tracks_index_and_removal() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let df = term_document_frequency(&conn, "lorem")?;
+        assert!(df > 0);
+
+        let id = Uuid::try_parse(ID_STR)?;
+        snip::remove_snip(&conn, id, None)?;
+
+        let df_after = term_document_frequency(&conn, "lorem")?;
+        assert!(df_after <= df);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_analyzer_overrides_stemming() {
+        struct UppercaseAnalyzer;
+        impl Analyzer for UppercaseAnalyzer {
+            fn stem(&self, word: &str) -> String {
+                word.to_uppercase()
+            }
+        }
+
+        assert_eq!(stem_word("Documenting"), "document");
+
+        set_analyzer(Box::new(UppercaseAnalyzer));
+        assert_eq!(stem_word("Documenting"), "DOCUMENTING");
+
+        // restore the default so other tests in this process aren't affected
+        set_analyzer(Box::new(SnowballAnalyzer::default()));
+        assert_eq!(stem_word("Documenting"), "document");
+    }
+
+    #[test]
+    fn test_is_stop_word_default_set() {
+        assert!(is_stop_word("the"));
+        assert!(!is_stop_word("lorem"));
+    }
+
+    #[test]
+    fn test_set_stop_words_replaces_active_set() {
+        let original: HashSet<String> = ["the", "a", "an", "and", "are", "as", "at", "be", "by",
+            "for", "from", "ha", "he", "in", "is", "it", "it'", "of", "on", "that", "to", "wa",
+            "were", "will", "with"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        set_stop_words(["lorem".to_string()].into_iter().collect());
+        assert!(is_stop_word("lorem"));
+        assert!(!is_stop_word("the"));
+
+        // restore the default set so other tests in this process aren't affected
+        set_stop_words(original);
+    }
 }
\ No newline at end of file
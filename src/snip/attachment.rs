@@ -1,10 +1,19 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
 use chrono::{DateTime, FixedOffset};
+use rand::RngCore;
 use rusqlite::{Connection, DatabaseName};
+use sha2::{Digest, Sha256};
 use std::error::Error;
-use std::io::Read;
-use std::path::Path;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
+/// Size of each chunk copied between the database blob and a reader/writer, so that
+/// streaming large attachments never holds more than one chunk in memory.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
 use crate::snip::SnipError;
 
 /// Attachment represents binary data attached to a document
@@ -15,17 +24,257 @@ pub struct Attachment {
     pub name: String,
     pub data: Vec<u8>,
     pub size: usize,
+    pub hash: String,
+    pub media_type: Option<String>,
+    pub dim_x: Option<u32>,
+    pub dim_y: Option<u32>,
+    /// The source file's modification time at the point it was attached, distinct from
+    /// `timestamp` (which records when the row was inserted). `None` for attachments
+    /// added before this was tracked, or added via a reader with no backing file.
+    pub source_mtime: Option<DateTime<FixedOffset>>,
 }
 
-/// Returns an Attachment struct parsed from the database
-fn attachment_data_from_db(conn: &Connection, row_id: i64) -> Result<Vec<u8>, Box<dyn Error>> {
+impl Attachment {
+    /// Returns a shortened, base58-encoded form of the content hash suitable for
+    /// display or addressing: the first 12 characters of `hash_b58(&self.hash)`.
+    pub fn short_hash(&self) -> Result<String, Box<dyn Error>> {
+        let encoded = hash_b58(&self.hash)?;
+        Ok(encoded.chars().take(12).collect())
+    }
+
+    /// Extracts plain text from this attachment's data, dispatching on the detected
+    /// media type, so the search index can be extended with text that only appears
+    /// inside an attached file rather than the document body. Returns `None` for media
+    /// types with no known extraction (e.g. images) rather than an error.
+    pub fn extract_text(&self) -> Result<Option<String>, Box<dyn Error>> {
+        match self.media_type.as_deref() {
+            Some("text/plain") => Ok(Some(String::from_utf8_lossy(&self.data).to_string())),
+            Some("text/html") => Ok(Some(strip_html_tags(&String::from_utf8_lossy(&self.data)))),
+            Some("application/pdf") => Ok(pdf_extract::extract_text_from_mem(&self.data).ok()),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Strips HTML tags from `html`, leaving only the text between them. This is a minimal,
+/// non-validating scanner intended for search indexing, not a general HTML parser.
+fn strip_html_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Metadata about an attachment's content, without the blob itself.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AttachmentMetadata {
+    pub media_type: Option<String>,
+    pub dim_x: Option<u32>,
+    pub dim_y: Option<u32>,
+}
+
+/// Sniffs the magic bytes at the start of `data` and returns a best-guess MIME type.
+/// Returns `None` for unrecognized binary data; falls back to `text/plain` for data
+/// that decodes as UTF-8.
+pub fn sniff_media_type(data: &[u8]) -> Option<String> {
+    if data.starts_with(b"%PDF") {
+        return Some("application/pdf".to_string());
+    }
+    if data.starts_with(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]) {
+        return Some("image/png".to_string());
+    }
+    if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        return Some("image/jpeg".to_string());
+    }
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return Some("image/gif".to_string());
+    }
+    if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp".to_string());
+    }
+    if std::str::from_utf8(data).is_ok() {
+        return Some("text/plain".to_string());
+    }
+    None
+}
+
+/// Parses pixel dimensions from an image header, for media types where this can be
+/// done without decoding the whole image. Returns `None` for unsupported types.
+pub fn sniff_dimensions(media_type: &str, data: &[u8]) -> Option<(u32, u32)> {
+    match media_type {
+        "image/png" => {
+            // PNG: width/height are big-endian u32 at file offset 16/20, the start of the
+            // IHDR chunk's data
+            if data.len() < 24 {
+                return None;
+            }
+            let width = u32::from_be_bytes(data[16..20].try_into().ok()?);
+            let height = u32::from_be_bytes(data[20..24].try_into().ok()?);
+            Some((width, height))
+        }
+        "image/gif" => {
+            // GIF: width/height are little-endian u16 right after the 6-byte signature
+            if data.len() < 10 {
+                return None;
+            }
+            let width = u16::from_le_bytes(data[6..8].try_into().ok()?) as u32;
+            let height = u16::from_le_bytes(data[8..10].try_into().ok()?) as u32;
+            Some((width, height))
+        }
+        "image/jpeg" => sniff_jpeg_dimensions(data),
+        _ => None,
+    }
+}
+
+/// Scans JPEG SOFn markers for the frame dimensions.
+fn sniff_jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut i = 2; // skip the SOI marker (0xff 0xd8)
+    while i + 9 < data.len() {
+        if data[i] != 0xff {
+            i += 1;
+            continue;
+        }
+        let marker = data[i + 1];
+        // SOF0-SOF3, SOF5-SOF7, SOF9-SOF11, SOF13-SOF15 carry dimensions
+        let is_sof = matches!(marker, 0xc0..=0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf);
+        if is_sof {
+            let height = u16::from_be_bytes(data[i + 5..i + 7].try_into().ok()?) as u32;
+            let width = u16::from_be_bytes(data[i + 7..i + 9].try_into().ok()?) as u32;
+            return Some((width, height));
+        }
+        let segment_len = u16::from_be_bytes(data[i + 2..i + 4].try_into().ok()?) as usize;
+        i += 2 + segment_len;
+    }
+    None
+}
+
+/// Indicates whether an attachment's blob was newly stored or deduplicated against
+/// an existing attachment with identical content.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AttachmentDedupStatus {
+    Stored,
+    Deduplicated,
+}
+
+/// Computes the SHA-256 hash of attachment data, as a lowercase hex string.
+pub fn hash_data(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hex::encode(hasher.finalize())
+}
+
+/// Encodes a hex content hash (as returned by `hash_data`) as base58, for a shorter
+/// display/CLI address akin to the blob addresses upend prints. Returns the full
+/// base58 string; callers that want a short form can truncate it themselves.
+pub fn hash_b58(hash_hex: &str) -> Result<String, Box<dyn Error>> {
+    let bytes = hex::decode(hash_hex)?;
+    Ok(bs58::encode(bytes).into_string())
+}
+
+/// Streams the blob for the data-owning row `row_id` to `writer` in fixed-size chunks,
+/// never holding more than one chunk in memory. Returns the number of bytes copied.
+fn stream_blob_to<W: Write>(conn: &Connection, row_id: i64, writer: &mut W) -> Result<u64, Box<dyn Error>> {
     let mut blob = conn.blob_open(DatabaseName::Main, "snip_attachment", "data", row_id, true)?;
-    let mut data: Vec<u8> = Vec::new();
+    let mut buf = [0u8; STREAM_CHUNK_SIZE];
+    let mut total: u64 = 0;
+
+    loop {
+        let n = blob.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n])?;
+        total += n as u64;
+    }
+    Ok(total)
+}
+
+/// Streams an attachment's blob to `writer`, following a dedup reference to the owning
+/// row if necessary. Intended for large attachments where materializing the whole blob
+/// in a `Vec<u8>` is undesirable.
+pub fn read_attachment_to<W: Write>(conn: &Connection, id: Uuid, writer: &mut W) -> Result<u64, Box<dyn Error>> {
+    let row_id: i64 = conn.query_row(
+        "SELECT rowid FROM snip_attachment WHERE uuid = :id",
+        &[(":id", &id.to_string())],
+        |row| row.get(0),
+    )?;
+    let owner_row_id = attachment_data_owner_row_id(conn, row_id)?;
+    stream_blob_to(conn, owner_row_id, writer)
+}
+
+/// One attachment whose re-hashed content no longer matches what was recorded at
+/// insert time, as reported by `verify_attachments`.
+#[derive(Debug)]
+pub struct AttachmentVerification {
+    pub uuid: Uuid,
+    pub name: String,
+    pub expected_hash: String,
+    pub actual_hash: String,
+}
+
+/// Re-hashes every stored attachment blob and returns the ones whose digest no longer
+/// matches the `hash` recorded at insert time, catching silent bit-rot or corruption in
+/// the underlying database file. Encrypted attachments (identified by a non-NULL `akey`)
+/// are skipped, since `hash` is only ever computed over plaintext and an encrypted row's
+/// ciphertext is expected to differ from it.
+pub fn verify_attachments(conn: &Connection) -> Result<Vec<AttachmentVerification>, Box<dyn Error>> {
+    let mut stmt =
+        conn.prepare("SELECT uuid, name, hash FROM snip_attachment WHERE akey IS NULL")?;
+    let rows = stmt.query_and_then([], |row| {
+        Ok::<(String, String, Option<String>), rusqlite::Error>((row.get(0)?, row.get(1)?, row.get(2)?))
+    })?;
+
+    let mut mismatches = Vec::new();
+    for row in rows.flatten() {
+        let (uuid_str, name, expected_hash) = row;
+        let expected_hash = match expected_hash {
+            Some(h) if !h.is_empty() => h,
+            _ => continue,
+        };
 
-    let _bytes_read = blob.read_to_end(&mut data)?;
+        let uuid = Uuid::try_parse(&uuid_str)?;
+        let mut data = Vec::new();
+        read_attachment_to(conn, uuid, &mut data)?;
+        let actual_hash = hash_data(&data);
+
+        if actual_hash != expected_hash {
+            mismatches.push(AttachmentVerification {
+                uuid,
+                name,
+                expected_hash,
+                actual_hash,
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Returns the raw blob data stored in row `row_id`. Thin wrapper over
+/// `stream_blob_to` for callers that want the whole blob materialized at once.
+fn attachment_data_from_db(conn: &Connection, row_id: i64) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut data: Vec<u8> = Vec::new();
+    stream_blob_to(conn, row_id, &mut data)?;
     Ok(data)
 }
 
+/// Returns the rowid of the data row that actually owns the blob for `row_id`,
+/// following `data_ref` when the row is a dedup reference.
+fn attachment_data_owner_row_id(conn: &Connection, row_id: i64) -> Result<i64, Box<dyn Error>> {
+    let data_ref: Option<i64> = conn.query_row(
+        "SELECT data_ref FROM snip_attachment WHERE rowid = :row_id",
+        &[(":row_id", &row_id)],
+        |row| row.get(0),
+    )?;
+    Ok(data_ref.unwrap_or(row_id))
+}
+
 /// Returns an Attachment struct parsed from the database
 fn attachment_from_db(
     uuid: String,
@@ -33,11 +282,19 @@ fn attachment_from_db(
     timestamp: String,
     name: String,
     size: usize,
+    hash: String,
+    media_type: Option<String>,
+    dim_x: Option<u32>,
+    dim_y: Option<u32>,
+    source_mtime: Option<String>,
     data: Vec<u8>,
 ) -> Result<Attachment, Box<dyn Error>> {
     let uuid = Uuid::try_parse(uuid.as_str())?;
     let snip_uuid = Uuid::try_parse(snip_uuid.as_str())?;
     let timestamp = DateTime::parse_from_rfc3339(timestamp.as_str())?;
+    let source_mtime = source_mtime
+        .map(|s| DateTime::parse_from_rfc3339(s.as_str()))
+        .transpose()?;
 
     Ok(Attachment {
         uuid,
@@ -45,19 +302,73 @@ fn attachment_from_db(
         timestamp,
         name,
         size,
+        hash,
+        source_mtime,
+        media_type,
+        dim_x,
+        dim_y,
         data,
     })
 }
 
-/// Add an attachment to the database and attach to supplied document Uuid
-pub fn add_attachment(conn: &Connection, snip_uuid: Uuid, path: &Path) -> Result<(), Box<dyn Error>> {
-    // check existence of file
-    let uuid = Uuid::new_v4();
-    let timestamp_utc = chrono::Utc::now();
-    let timestamp = timestamp_utc.fixed_offset();
+/// Returns the rowid of the attachment owning the blob matching `hash`, if one exists.
+/// Only rows that own their own data (`data_ref IS NULL`) are ever returned, so callers
+/// can insert a dedup reference that points directly at the owning blob.
+pub fn get_attachment_by_hash(conn: &Connection, hash: &str) -> Result<Option<i64>, Box<dyn Error>> {
+    let mut stmt = conn.prepare(
+        "SELECT rowid FROM snip_attachment WHERE hash = :hash AND data_ref IS NULL",
+    )?;
+    let mut rows = stmt.query_and_then(&[(":hash", &hash)], |row| -> Result<i64, Box<dyn Error>> {
+        Ok(row.get(0)?)
+    })?;
+
+    match rows.next() {
+        Some(row_id) => Ok(Some(row_id?)),
+        None => Ok(None),
+    }
+}
+
+/// Add an attachment to the database and attach to supplied document Uuid.
+/// If the file's content hash matches an existing attachment, only a metadata row
+/// is written and the blob is shared to avoid storing duplicate data.
+pub fn add_attachment(
+    conn: &Connection,
+    snip_uuid: Uuid,
+    path: &Path,
+) -> Result<AttachmentDedupStatus, Box<dyn Error>> {
     let name = path.file_name().ok_or("parsing attachment basename")?.to_string_lossy().to_string();
+    let source_mtime = path
+        .metadata()
+        .and_then(|m| m.modified())
+        .ok()
+        .map(|t| DateTime::<chrono::Utc>::from(t).fixed_offset());
     let data = std::fs::read(path)?;
+    insert_attachment(conn, snip_uuid, name, data, source_mtime)
+}
+
+/// Inserts a new attachment row for `data` already read into memory, sniffing its media
+/// type/dimensions, hashing it, and deduplicating against existing blobs by content
+/// hash. Shared by `add_attachment` and the bulk directory importer, which reads files in
+/// parallel before handing them to this serial, single-connection insertion path.
+fn insert_attachment(
+    conn: &Connection,
+    snip_uuid: Uuid,
+    name: String,
+    data: Vec<u8>,
+    source_mtime: Option<DateTime<FixedOffset>>,
+) -> Result<AttachmentDedupStatus, Box<dyn Error>> {
+    let uuid = Uuid::new_v4();
+    let timestamp = chrono::Utc::now().fixed_offset();
     let size = data.len();
+    let hash = hash_data(&data);
+    let media_type = sniff_media_type(&data);
+    let (dim_x, dim_y) = match &media_type {
+        Some(mt) => match sniff_dimensions(mt, &data) {
+            Some((x, y)) => (Some(x), Some(y)),
+            None => (None, None),
+        },
+        None => (None, None),
+    };
 
     // assign new Attachment
     let a = Attachment {
@@ -67,43 +378,464 @@ pub fn add_attachment(conn: &Connection, snip_uuid: Uuid, path: &Path) -> Result
         name,
         data,
         size,
+        hash,
+        media_type,
+        dim_x,
+        dim_y,
+        source_mtime,
     };
 
+    // if an attachment with identical content already exists, write only a metadata
+    // row that references the existing blob instead of storing the data again
+    let dim_x_i64 = a.dim_x.map(|v| v as i64);
+    let dim_y_i64 = a.dim_y.map(|v| v as i64);
+    let source_mtime_str = a.source_mtime.map(|t| t.to_rfc3339());
+
+    if let Some(owner_row_id) = get_attachment_by_hash(conn, &a.hash)? {
+        let mut stmt = conn.prepare("INSERT INTO snip_attachment(uuid, snip_uuid, timestamp, name, data, size, hash, data_ref, media_type, dim_x, dim_y, source_mtime) VALUES(:uuid, :snip_uuid, :timestamp, :name, NULL, :size, :hash, :data_ref, :media_type, :dim_x, :dim_y, :source_mtime)")?;
+        let result = stmt.execute(rusqlite::named_params! {
+            ":uuid": &a.uuid.to_string(),
+            ":snip_uuid": &a.snip_uuid.to_string(),
+            ":timestamp": &a.timestamp.to_rfc3339(),
+            ":name": &a.name,
+            ":size": &a.size.to_string(),
+            ":hash": &a.hash,
+            ":data_ref": &owner_row_id.to_string(),
+            ":media_type": &a.media_type,
+            ":dim_x": &dim_x_i64,
+            ":dim_y": &dim_y_i64,
+            ":source_mtime": &source_mtime_str,
+        })?;
+        assert_eq!(result, 1);
+        return Ok(AttachmentDedupStatus::Deduplicated);
+    }
+
     // insert
+    let mut stmt = conn.prepare("INSERT INTO snip_attachment(uuid, snip_uuid, timestamp, name, data, size, hash, media_type, dim_x, dim_y, source_mtime) VALUES(:uuid, :snip_uuid, :timestamp, :name, ZEROBLOB(:size), :size, :hash, :media_type, :dim_x, :dim_y, :source_mtime)")?;
+    let result = stmt.execute(rusqlite::named_params! {
+        ":uuid": &a.uuid.to_string(),
+        ":snip_uuid": &a.snip_uuid.to_string(),
+        ":timestamp": &a.timestamp.to_rfc3339(),
+        ":name": &a.name,
+        ":size": &a.size.to_string(),
+        ":hash": &a.hash,
+        ":media_type": &a.media_type,
+        ":dim_x": &dim_x_i64,
+        ":dim_y": &dim_y_i64,
+        ":source_mtime": &source_mtime_str,
+    })?;
+    assert_eq!(result, 1);
+
+    // add blob data
+    let row_id = conn.last_insert_rowid();
+    let mut blob = conn.blob_open(DatabaseName::Main, "snip_attachment", "data", row_id, false)?;
+    blob.write_at(a.data.as_slice(), 0)?;
+    Ok(AttachmentDedupStatus::Stored)
+}
+
+/// Per-file outcome of `add_attachments_from_dir`.
+#[derive(Debug)]
+pub enum DirImportOutcome {
+    Added,
+    Deduplicated,
+    Skipped,
+    Errored(String),
+}
+
+/// Summarizes the result of importing a single file during a bulk directory import.
+#[derive(Debug)]
+pub struct DirImportEntry {
+    pub relative_path: String,
+    pub outcome: DirImportOutcome,
+}
+
+/// Walks `root` recursively and attaches every regular file to `snip_uuid`, reading file
+/// contents in parallel across a rayon worker pool, then hashing and inserting rows
+/// serially on the single SQLite connection; the hashing is not parallelized. Each attachment's `name` is set to its path relative to
+/// `root`, so the original directory structure can be recovered later. When `extensions`
+/// is `Some`, only files whose extension matches (case-insensitively) are attached;
+/// everything else is reported as `Skipped`.
+pub fn add_attachments_from_dir(
+    conn: &Connection,
+    snip_uuid: Uuid,
+    root: &Path,
+    extensions: Option<&[&str]>,
+) -> Result<Vec<DirImportEntry>, Box<dyn Error>> {
+    let mut candidates: Vec<std::path::PathBuf> = Vec::new();
+    let mut entries: Vec<DirImportEntry> = Vec::new();
+
+    for entry in walkdir::WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.into_path();
+        let relative_path = path
+            .strip_prefix(root)
+            .unwrap_or(path.as_path())
+            .to_string_lossy()
+            .to_string();
+
+        let matches_filter = match extensions {
+            Some(exts) => path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(|ext| exts.iter().any(|wanted| wanted.eq_ignore_ascii_case(ext)))
+                .unwrap_or(false),
+            None => true,
+        };
+
+        if !matches_filter {
+            entries.push(DirImportEntry {
+                relative_path,
+                outcome: DirImportOutcome::Skipped,
+            });
+            continue;
+        }
+        candidates.push(path);
+    }
+
+    // read file contents (and source mtimes) in parallel; this is pure IO work that does
+    // not touch the database, so it is safe to fan out across a rayon worker pool
+    use rayon::prelude::*;
+    let hashed: Vec<(std::path::PathBuf, std::io::Result<Vec<u8>>, Option<DateTime<FixedOffset>>)> = candidates
+        .into_par_iter()
+        .map(|path| {
+            let source_mtime = path.metadata().and_then(|m| m.modified()).ok().map(|t| DateTime::<chrono::Utc>::from(t).fixed_offset());
+            let data = std::fs::read(&path);
+            (path, data, source_mtime)
+        })
+        .collect();
+
+    // insert rows serially; rusqlite::Connection is not Sync, so this part stays single-threaded
+    for (path, data, source_mtime) in hashed {
+        let relative_path = path
+            .strip_prefix(root)
+            .unwrap_or(path.as_path())
+            .to_string_lossy()
+            .to_string();
+
+        let outcome = match data {
+            Err(e) => DirImportOutcome::Errored(e.to_string()),
+            Ok(data) => match insert_attachment(conn, snip_uuid, relative_path.clone(), data, source_mtime) {
+                Ok(AttachmentDedupStatus::Stored) => DirImportOutcome::Added,
+                Ok(AttachmentDedupStatus::Deduplicated) => DirImportOutcome::Deduplicated,
+                Err(e) => DirImportOutcome::Errored(e.to_string()),
+            },
+        };
+        entries.push(DirImportEntry {
+            relative_path,
+            outcome,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Adds an attachment to the database by streaming `reader` directly into the blob in
+/// fixed-size chunks, never holding more than one chunk in memory. `size` must be the
+/// exact byte length `reader` will yield, since the blob is pre-allocated with
+/// `ZEROBLOB` before any data is copied. Unlike `add_attachment`, this does not sniff
+/// the media type or perform content-hash deduplication, since both would require
+/// buffering the whole file.
+pub fn add_attachment_from_reader<R: Read>(
+    conn: &Connection,
+    snip_uuid: Uuid,
+    name: String,
+    size: usize,
+    reader: &mut R,
+) -> Result<Uuid, Box<dyn Error>> {
+    let uuid = Uuid::new_v4();
+    let timestamp = chrono::Utc::now().fixed_offset();
+
     let mut stmt = conn.prepare("INSERT INTO snip_attachment(uuid, snip_uuid, timestamp, name, data, size) VALUES(:uuid, :snip_uuid, :timestamp, :name, ZEROBLOB(:size), :size)")?;
     let result = stmt.execute(&[
-        (":uuid", &a.uuid.to_string()),
-        (":snip_uuid", &a.snip_uuid.to_string()),
-        (":timestamp", &a.timestamp.to_rfc3339().to_string()),
-        (":name", &a.name.to_string()),
-        (":size", &a.size.to_string()),
+        (":uuid", &uuid.to_string()),
+        (":snip_uuid", &snip_uuid.to_string()),
+        (":timestamp", &timestamp.to_rfc3339()),
+        (":name", &name),
+        (":size", &size.to_string()),
     ])?;
     assert_eq!(result, 1);
 
-    // add blob data
     let row_id = conn.last_insert_rowid();
     let mut blob = conn.blob_open(DatabaseName::Main, "snip_attachment", "data", row_id, false)?;
-    blob.write_at(a.data.as_slice(), 0)?;
-    Ok(())
+
+    let mut buf = [0u8; STREAM_CHUNK_SIZE];
+    let mut hasher = Sha256::new();
+    let mut offset: usize = 0;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        blob.write_at(&buf[..n], offset)?;
+        hasher.update(&buf[..n]);
+        offset += n;
+    }
+
+    let hash = hex::encode(hasher.finalize());
+    conn.execute(
+        "UPDATE snip_attachment SET hash = :hash WHERE uuid = :uuid",
+        &[(":hash", &hash), (":uuid", &uuid.to_string())],
+    )?;
+
+    Ok(uuid)
+}
+
+/// Length in bytes of the random, per-attachment salt `derive_key` is stretched with.
+const DERIVE_KEY_SALT_LEN: usize = 16;
+
+/// Derives a 32-byte symmetric key from a user-supplied passphrase and a random
+/// `salt`, stretched through Argon2id so recovering the key from a stolen database
+/// requires running the KDF per guess rather than one cheap hash per guess.
+fn derive_key(passphrase: &str, salt: &[u8; DERIVE_KEY_SALT_LEN]) -> Result<[u8; 32], Box<dyn Error>> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| SnipError::General(format!("deriving attachment key: {}", e)))?;
+    Ok(key)
+}
+
+/// Encrypts `plaintext` with XChaCha20-Poly1305 keyed from `passphrase` via `derive_key`.
+/// Returns the random salt and nonce concatenated (stored in the `akey` column) and the
+/// ciphertext with its authentication tag appended (stored in the `data` blob).
+fn encrypt_attachment_data(passphrase: &str, plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Box<dyn Error>> {
+    let mut salt = [0u8; DERIVE_KEY_SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let cipher = XChaCha20Poly1305::new(&derive_key(passphrase, &salt)?.into());
+
+    let mut nonce_bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut nonce_bytes);
+    let nonce = XNonce::from_slice(&nonce_bytes);
+
+    let ciphertext = cipher
+        .encrypt(nonce, plaintext)
+        .map_err(|e| SnipError::General(format!("encrypting attachment data: {}", e)))?;
+
+    let mut akey = Vec::with_capacity(DERIVE_KEY_SALT_LEN + nonce_bytes.len());
+    akey.extend_from_slice(&salt);
+    akey.extend_from_slice(&nonce_bytes);
+
+    Ok((akey, ciphertext))
+}
+
+/// Decrypts data previously produced by `encrypt_attachment_data`. Fails cleanly with
+/// `SnipError::DecryptionFailed` on a wrong passphrase or tampered ciphertext, since an
+/// authentication tag mismatch is the only signal AEAD decryption gives us.
+fn decrypt_attachment_data(passphrase: &str, akey: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if akey.len() != DERIVE_KEY_SALT_LEN + 24 {
+        return Err(Box::new(SnipError::DecryptionFailed(
+            "stored salt/nonce has unexpected length".to_string(),
+        )));
+    }
+    let (salt, nonce_bytes) = akey.split_at(DERIVE_KEY_SALT_LEN);
+    let salt: [u8; DERIVE_KEY_SALT_LEN] = salt.try_into().expect("split_at guarantees length");
+    let cipher = XChaCha20Poly1305::new(&derive_key(passphrase, &salt)?.into());
+    let nonce = XNonce::from_slice(nonce_bytes);
+
+    cipher
+        .decrypt(nonce, ciphertext)
+        .map_err(|_| Box::new(SnipError::DecryptionFailed(
+            "wrong passphrase or corrupted attachment data".to_string(),
+        )) as Box<dyn Error>)
+}
+
+/// Adds an encrypted attachment to the database. The blob is stored as XChaCha20-Poly1305
+/// ciphertext keyed from `passphrase` via Argon2id, with the salt and nonce kept
+/// alongside it in the `akey` column. Encrypted attachments are not eligible for
+/// content-addressed deduplication, since each encryption uses a fresh random salt and
+/// nonce.
+pub fn add_attachment_encrypted(
+    conn: &Connection,
+    snip_uuid: Uuid,
+    path: &Path,
+    passphrase: &str,
+) -> Result<Uuid, Box<dyn Error>> {
+    let uuid = Uuid::new_v4();
+    let timestamp = chrono::Utc::now().fixed_offset();
+    let name = path.file_name().ok_or("parsing attachment basename")?.to_string_lossy().to_string();
+    let plaintext = std::fs::read(path)?;
+    let size = plaintext.len();
+
+    let (akey, ciphertext) = encrypt_attachment_data(passphrase, &plaintext)?;
+
+    let mut stmt = conn.prepare("INSERT INTO snip_attachment(uuid, snip_uuid, timestamp, name, data, size, akey) VALUES(:uuid, :snip_uuid, :timestamp, :name, :data, :size, :akey)")?;
+    let result = stmt.execute(rusqlite::named_params! {
+        ":uuid": &uuid.to_string(),
+        ":snip_uuid": &snip_uuid.to_string(),
+        ":timestamp": &timestamp.to_rfc3339(),
+        ":name": &name,
+        ":data": &ciphertext,
+        ":size": &size.to_string(),
+        ":akey": &akey,
+    })?;
+    assert_eq!(result, 1);
+
+    Ok(uuid)
+}
+
+/// Adds an encrypted attachment tagged with `key_id`, a caller-chosen label identifying
+/// which key was used (e.g. a key management system's key reference), mirroring
+/// vaultwarden's per-attachment `akey` model where the stored key material is paired
+/// with a reference rather than assumed to be the one true key. Otherwise identical to
+/// `add_attachment_encrypted`.
+pub fn add_attachment_encrypted_with_key_id(
+    conn: &Connection,
+    snip_uuid: Uuid,
+    path: &Path,
+    passphrase: &str,
+    key_id: &str,
+) -> Result<Uuid, Box<dyn Error>> {
+    let uuid = Uuid::new_v4();
+    let timestamp = chrono::Utc::now().fixed_offset();
+    let name = path.file_name().ok_or("parsing attachment basename")?.to_string_lossy().to_string();
+    let plaintext = std::fs::read(path)?;
+    let size = plaintext.len();
+
+    let (akey, ciphertext) = encrypt_attachment_data(passphrase, &plaintext)?;
+
+    let mut stmt = conn.prepare("INSERT INTO snip_attachment(uuid, snip_uuid, timestamp, name, data, size, akey, key_id) VALUES(:uuid, :snip_uuid, :timestamp, :name, :data, :size, :akey, :key_id)")?;
+    let result = stmt.execute(rusqlite::named_params! {
+        ":uuid": &uuid.to_string(),
+        ":snip_uuid": &snip_uuid.to_string(),
+        ":timestamp": &timestamp.to_rfc3339(),
+        ":name": &name,
+        ":data": &ciphertext,
+        ":size": &size.to_string(),
+        ":akey": &akey,
+        ":key_id": &key_id,
+    })?;
+    assert_eq!(result, 1);
+
+    Ok(uuid)
+}
+
+/// Returns the `key_id` tagged on an encrypted attachment, if any.
+pub fn get_attachment_key_id(conn: &Connection, id: Uuid) -> Result<Option<String>, Box<dyn Error>> {
+    conn.query_row(
+        "SELECT key_id FROM snip_attachment WHERE uuid = :id",
+        &[(":id", &id.to_string())],
+        |row| row.get(0),
+    )
+    .map_err(|e| Box::new(e) as Box<dyn Error>)
+}
+
+/// Gets an encrypted attachment, first verifying that `key_id` matches the one it was
+/// tagged with at encryption time. Fails with `SnipError::KeyMismatch` before even
+/// attempting decryption if the labels disagree, rather than surfacing a generic
+/// authentication-tag failure for what is really a wrong-key-selected error.
+pub fn get_attachment_from_uuid_with_key(
+    conn: &Connection,
+    id: Uuid,
+    key_id: &str,
+    passphrase: &str,
+) -> Result<Attachment, Box<dyn Error>> {
+    let stored_key_id = get_attachment_key_id(conn, id)?;
+    if stored_key_id.as_deref() != Some(key_id) {
+        return Err(Box::new(SnipError::KeyMismatch(format!(
+            "attachment {} was encrypted with a different key_id",
+            id
+        ))));
+    }
+    get_attachment_from_uuid_with_passphrase(conn, id, passphrase)
+}
+
+/// Gets a (possibly encrypted) attachment from the database, decrypting it if an `akey`
+/// is present. Unencrypted attachments (`akey` is `NULL`) are returned unchanged, so this
+/// is a drop-in replacement for `get_attachment_from_uuid` once a passphrase is known.
+pub fn get_attachment_from_uuid_with_passphrase(
+    conn: &Connection,
+    id: Uuid,
+    passphrase: &str,
+) -> Result<Attachment, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT uuid, snip_uuid, timestamp, name, size, rowid, hash, media_type, dim_x, dim_y, akey, source_mtime FROM snip_attachment WHERE uuid = :id")?;
+    let mut rows = stmt.query_and_then(&[(":id", &id.to_string())], |row| {
+        let row_id: i64 = row.get(5)?;
+        let akey: Option<Vec<u8>> = row.get(10)?;
+
+        let data = match &akey {
+            Some(akey) => {
+                let ciphertext = attachment_data_from_db(conn, row_id)?;
+                decrypt_attachment_data(passphrase, akey, &ciphertext)?
+            }
+            None => {
+                let owner_row_id = attachment_data_owner_row_id(conn, row_id)?;
+                attachment_data_from_db(conn, owner_row_id)?
+            }
+        };
+
+        let hash: Option<String> = row.get(6)?;
+        let media_type: Option<String> = row.get(7)?;
+        let dim_x: Option<i64> = row.get(8)?;
+        let dim_y: Option<i64> = row.get(9)?;
+        let source_mtime: Option<String> = row.get(11)?;
+        attachment_from_db(
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            hash.unwrap_or_default(),
+            media_type,
+            dim_x.map(|v| v as u32),
+            dim_y.map(|v| v as u32),
+            source_mtime,
+            data,
+        )
+    })?;
+
+    match rows.next() {
+        Some(a) => a,
+        None => Err(Box::new(SnipError::UuidNotFound(
+            "could not find uuid".to_string(),
+        ))),
+    }
 }
 
 /// Get an attachment from database
 pub fn get_attachment_from_uuid(conn: &Connection, id: Uuid) -> Result<Attachment, Box<dyn Error>> {
     // get metadata
-    let mut stmt = conn
-        .prepare("SELECT uuid, snip_uuid, timestamp, name, size, rowid FROM snip_attachment WHERE uuid = :id")?;
+    let mut stmt = conn.prepare("SELECT uuid, snip_uuid, timestamp, name, size, rowid, hash, media_type, dim_x, dim_y, source_mtime FROM snip_attachment WHERE uuid = :id")?;
     let mut rows = stmt.query_and_then(&[(":id", &id.to_string())], |row| {
-        // read data first using rowid
+        // follow data_ref (if set) to the rowid that actually owns the blob
         let row_id: i64 = row.get(5)?;
-        let data = attachment_data_from_db(conn, row_id)?;
-        attachment_from_db(row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, data)
+        let owner_row_id = attachment_data_owner_row_id(conn, row_id)?;
+        let data = attachment_data_from_db(conn, owner_row_id)?;
+        let hash: Option<String> = row.get(6)?;
+        let media_type: Option<String> = row.get(7)?;
+        let dim_x: Option<i64> = row.get(8)?;
+        let dim_y: Option<i64> = row.get(9)?;
+        let source_mtime: Option<String> = row.get(10)?;
+        attachment_from_db(
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            hash.unwrap_or_default(),
+            media_type,
+            dim_x.map(|v| v as u32),
+            dim_y.map(|v| v as u32),
+            source_mtime,
+            data,
+        )
     })?;
 
     if let Some(a) = rows.next() {
-        let attachment = match a {
+        let mut attachment = match a {
             Ok(v) => v,
             Err(e) => return Err(e),
         };
+
+        // migrate rows written before content hashing existed by computing the
+        // hash lazily on first read and persisting it
+        if attachment.hash.is_empty() {
+            attachment.hash = hash_data(&attachment.data);
+            conn.execute(
+                "UPDATE snip_attachment SET hash = :hash WHERE uuid = :uuid",
+                &[(":hash", &attachment.hash), (":uuid", &attachment.uuid.to_string())],
+            )?;
+        }
         return Ok(attachment);
     }
 
@@ -113,6 +845,29 @@ pub fn get_attachment_from_uuid(conn: &Connection, id: Uuid) -> Result<Attachmen
     )))
 }
 
+/// Returns the media type and dimensions of an attachment without reading its blob,
+/// so CLI output and preview code can filter by media kind cheaply.
+pub fn get_attachment_metadata(conn: &Connection, id: Uuid) -> Result<AttachmentMetadata, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT media_type, dim_x, dim_y FROM snip_attachment WHERE uuid = :id")?;
+    let mut rows = stmt.query_and_then(&[(":id", &id.to_string())], |row| {
+        let media_type: Option<String> = row.get(0)?;
+        let dim_x: Option<i64> = row.get(1)?;
+        let dim_y: Option<i64> = row.get(2)?;
+        Ok::<AttachmentMetadata, Box<dyn Error>>(AttachmentMetadata {
+            media_type,
+            dim_x: dim_x.map(|v| v as u32),
+            dim_y: dim_y.map(|v| v as u32),
+        })
+    })?;
+
+    match rows.next() {
+        Some(metadata) => metadata,
+        None => Err(Box::new(SnipError::UuidNotFound(
+            "could not find uuid".to_string(),
+        ))),
+    }
+}
+
 /// Return a vector of all attachment uuids
 pub fn get_attachment_all(conn: &Connection) -> Result<Vec<Uuid>, Box<dyn Error>> {
     let mut stmt = conn.prepare("SELECT uuid FROM snip_attachment")?;
@@ -127,6 +882,119 @@ pub fn get_attachment_all(conn: &Connection) -> Result<Vec<Uuid>, Box<dyn Error>
     Ok(ids)
 }
 
+/// Returns the uuids of all attachments whose media type matches `pattern`. A trailing
+/// `/*` matches any subtype, e.g. `"image/*"` matches `image/png` and `image/jpeg`;
+/// otherwise `pattern` must match the stored media type exactly, e.g. `"application/pdf"`.
+/// Attachments with no detected media type never match.
+pub fn get_attachments_by_mime(conn: &Connection, pattern: &str) -> Result<Vec<Uuid>, Box<dyn Error>> {
+    let like_pattern = match pattern.strip_suffix("/*") {
+        Some(prefix) => format!("{}/%", prefix),
+        None => pattern.to_string(),
+    };
+
+    let mut stmt = conn.prepare("SELECT uuid FROM snip_attachment WHERE media_type LIKE :pattern")?;
+    let query_iter = stmt.query_and_then(&[(":pattern", &like_pattern)], |row| row.get::<_, String>(0))?;
+
+    let mut ids: Vec<Uuid> = Vec::new();
+    for id in query_iter {
+        ids.push(Uuid::try_parse(id?.as_str())?);
+    }
+    Ok(ids)
+}
+
+/// Returns all attachment uuids belonging to the given document.
+fn get_attachment_all_for_snip(conn: &Connection, snip_uuid: Uuid) -> Result<Vec<Uuid>, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT uuid FROM snip_attachment WHERE snip_uuid = :snip_uuid")?;
+    let query_iter = stmt.query_and_then(&[(":snip_uuid", &snip_uuid.to_string())], |row| {
+        row.get::<_, String>(0)
+    })?;
+
+    let mut ids: Vec<Uuid> = Vec::new();
+    for id in query_iter.flatten() {
+        ids.push(Uuid::try_parse(id.as_str())?);
+    }
+    Ok(ids)
+}
+
+/// Writes an attachment's blob back to disk under `dest_dir`, using its stored `name`
+/// and restoring the recorded `timestamp` as the file's mtime. The inverse of
+/// `add_attachment`. If a file with that name already exists in `dest_dir`, the
+/// attachment's short uuid is appended to avoid a collision. Returns the path written.
+pub fn export_attachment(conn: &Connection, id: Uuid, dest_dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let a = get_attachment_from_uuid(conn, id)?;
+    std::fs::create_dir_all(dest_dir)?;
+
+    let mut dest = dest_dir.join(&a.name);
+    if dest.exists() {
+        let short_uuid = a.uuid.to_string()[..8].to_string();
+        let stem = Path::new(&a.name).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| a.name.clone());
+        let ext = Path::new(&a.name).extension().map(|e| e.to_string_lossy().to_string());
+        let collision_name = match ext {
+            Some(ext) => format!("{}.{}.{}", stem, short_uuid, ext),
+            None => format!("{}.{}", stem, short_uuid),
+        };
+        dest = dest_dir.join(collision_name);
+    }
+
+    std::fs::write(&dest, &a.data)?;
+    let mtime = filetime::FileTime::from_unix_time(a.timestamp.timestamp(), 0);
+    filetime::set_file_mtime(&dest, mtime)?;
+
+    Ok(dest)
+}
+
+/// Writes an attachment's blob back to disk under `dest_dir`, streaming it straight from
+/// the database blob in fixed-size chunks rather than materializing the whole payload via
+/// `get_attachment_from_uuid`. Otherwise behaves like `export_attachment`, including the
+/// short-uuid collision suffix and restored mtime. Prefer this over `export_attachment`
+/// for large attachments.
+pub fn export_attachment_streaming(conn: &Connection, id: Uuid, dest_dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT name, timestamp FROM snip_attachment WHERE uuid = :id")?;
+    let (name, timestamp): (String, String) = stmt.query_row(&[(":id", &id.to_string())], |row| {
+        Ok((row.get(0)?, row.get(1)?))
+    })?;
+    let timestamp = DateTime::parse_from_rfc3339(timestamp.as_str())?;
+
+    std::fs::create_dir_all(dest_dir)?;
+    let mut dest = dest_dir.join(&name);
+    if dest.exists() {
+        let short_uuid = id.to_string()[..8].to_string();
+        let stem = Path::new(&name).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| name.clone());
+        let ext = Path::new(&name).extension().map(|e| e.to_string_lossy().to_string());
+        let collision_name = match ext {
+            Some(ext) => format!("{}.{}.{}", stem, short_uuid, ext),
+            None => format!("{}.{}", stem, short_uuid),
+        };
+        dest = dest_dir.join(collision_name);
+    }
+
+    let mut file = std::fs::File::create(&dest)?;
+    read_attachment_to(conn, id, &mut file)?;
+    drop(file);
+
+    let mtime = filetime::FileTime::from_unix_time(timestamp.timestamp(), 0);
+    filetime::set_file_mtime(&dest, mtime)?;
+
+    Ok(dest)
+}
+
+/// Exports every attachment belonging to `snip_uuid` into `dest_dir/<snip_uuid>/`, so a
+/// whole document's attachments land together. Returns the paths written.
+pub fn export_attachments_for_snip(
+    conn: &Connection,
+    snip_uuid: Uuid,
+    dest_dir: &Path,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let doc_dir = dest_dir.join(snip_uuid.to_string());
+    let ids = get_attachment_all_for_snip(conn, snip_uuid)?;
+
+    let mut written = Vec::new();
+    for id in ids {
+        written.push(export_attachment(conn, id, &doc_dir)?);
+    }
+    Ok(written)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -151,6 +1019,302 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_add_attachment_dedup() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+
+        let snip_uuid = Uuid::try_parse(ID_STR)?;
+        let path = Path::new("test_data/attachments/udhr.pdf");
+        // the fixture data already attaches one file to this document; count only new rows
+        let existing = get_attachment_all(&conn)?;
+
+        let status_first = add_attachment(&conn, snip_uuid, path)?;
+        assert_eq!(status_first, AttachmentDedupStatus::Stored);
+
+        // attaching the same file again should be deduplicated against the first blob
+        let status_second = add_attachment(&conn, snip_uuid, path)?;
+        assert_eq!(status_second, AttachmentDedupStatus::Deduplicated);
+
+        let attachments = get_attachment_all(&conn)?;
+        let mut matched = 0;
+        for id in attachments {
+            if existing.contains(&id) {
+                continue;
+            }
+            let a = get_attachment_from_uuid(&conn, id)?;
+            if a.snip_uuid == snip_uuid {
+                matched += 1;
+                assert_eq!(a.data.len(), a.size);
+            }
+        }
+        assert_eq!(matched, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_attachments_detects_corrupted_blob() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+
+        let snip_uuid = Uuid::try_parse(ID_STR)?;
+        let path = Path::new("test_data/attachments/udhr.pdf");
+        add_attachment(&conn, snip_uuid, path)?;
+
+        // an untouched attachment verifies clean
+        assert!(verify_attachments(&conn)?.is_empty());
+
+        // corrupting the stored blob in place should surface as a hash mismatch; the
+        // fixture's own attachment for this document has no recorded hash, so target the
+        // row just added
+        let rowid: i64 = conn.query_row(
+            "SELECT rowid FROM snip_attachment WHERE snip_uuid = :snip_uuid AND hash IS NOT NULL",
+            &[(":snip_uuid", &snip_uuid.to_string())],
+            |row| row.get(0),
+        )?;
+        conn.execute(
+            "UPDATE snip_attachment SET data = X'00' WHERE rowid = :rowid",
+            &[(":rowid", &rowid)],
+        )?;
+
+        let mismatches = verify_attachments(&conn)?;
+        assert_eq!(mismatches.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_attachment_short_hash() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+
+        let snip_uuid = Uuid::try_parse(ID_STR)?;
+        let path = Path::new("test_data/attachments/udhr.pdf");
+        add_attachment(&conn, snip_uuid, path)?;
+
+        let attachments = get_attachment_all(&conn)?;
+        let id = *attachments.last().expect("at least one attachment");
+        let a = get_attachment_from_uuid(&conn, id)?;
+
+        let short = a.short_hash()?;
+        assert_eq!(short.len(), 12);
+        assert_eq!(short, a.short_hash()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sniff_media_type() {
+        assert_eq!(sniff_media_type(b"%PDF-1.4"), Some("application/pdf".to_string()));
+        assert_eq!(
+            sniff_media_type(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]),
+            Some("image/png".to_string())
+        );
+        assert_eq!(sniff_media_type(&[0xff, 0xd8, 0xff]), Some("image/jpeg".to_string()));
+        assert_eq!(sniff_media_type(b"GIF89a"), Some("image/gif".to_string()));
+        assert_eq!(sniff_media_type(b"hello world"), Some("text/plain".to_string()));
+        assert_eq!(sniff_media_type(&[0x00, 0xff, 0x10, 0x20]), None);
+    }
+
+    #[test]
+    fn test_add_attachment_media_type() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+
+        let snip_uuid = Uuid::try_parse(ID_STR)?;
+        let path = Path::new("test_data/attachments/udhr.pdf");
+        add_attachment(&conn, snip_uuid, path)?;
+
+        let attachments = get_attachment_all(&conn)?;
+        let id = *attachments.last().expect("at least one attachment");
+        let metadata = get_attachment_metadata(&conn, id)?;
+        assert_eq!(metadata.media_type, Some("application/pdf".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_attachment_from_reader_and_read_attachment_to() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+
+        let snip_uuid = Uuid::try_parse(ID_STR)?;
+        let original = std::fs::read("test_data/attachments/udhr.pdf")?;
+
+        let mut reader = original.as_slice();
+        let id = add_attachment_from_reader(
+            &conn,
+            snip_uuid,
+            "udhr-streamed.pdf".to_string(),
+            original.len(),
+            &mut reader,
+        )?;
+
+        let mut out: Vec<u8> = Vec::new();
+        let bytes_read = read_attachment_to(&conn, id, &mut out)?;
+
+        assert_eq!(bytes_read as usize, original.len());
+        assert_eq!(out, original);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_attachment_encrypted_roundtrip() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+
+        let snip_uuid = Uuid::try_parse(ID_STR)?;
+        let path = Path::new("test_data/attachments/udhr.pdf");
+        let passphrase = "correct horse battery staple";
+
+        let id = add_attachment_encrypted(&conn, snip_uuid, path, passphrase)?;
+        let a = get_attachment_from_uuid_with_passphrase(&conn, id, passphrase)?;
+
+        let expected = std::fs::read(path)?;
+        assert_eq!(a.data, expected);
+
+        // wrong passphrase should fail cleanly rather than return garbage
+        match get_attachment_from_uuid_with_passphrase(&conn, id, "wrong passphrase") {
+            Err(e) => assert!(matches!(*e.downcast::<SnipError>()?, SnipError::DecryptionFailed(_))),
+            Ok(_) => panic!("expected decryption to fail with the wrong passphrase"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_attachment_encrypted_with_key_id() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+
+        let snip_uuid = Uuid::try_parse(ID_STR)?;
+        let path = Path::new("test_data/attachments/udhr.pdf");
+        let passphrase = "correct horse battery staple";
+
+        let id = add_attachment_encrypted_with_key_id(&conn, snip_uuid, path, passphrase, "key-1")?;
+
+        let a = get_attachment_from_uuid_with_key(&conn, id, "key-1", passphrase)?;
+        let expected = std::fs::read(path)?;
+        assert_eq!(a.data, expected);
+
+        match get_attachment_from_uuid_with_key(&conn, id, "key-2", passphrase) {
+            Err(e) => assert!(matches!(*e.downcast::<SnipError>()?, SnipError::KeyMismatch(_))),
+            Ok(_) => panic!("expected a key_id mismatch to be rejected"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_attachments_from_dir() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        let snip_uuid = Uuid::try_parse(ID_STR)?;
+
+        // build a small directory tree to import
+        let root = std::env::temp_dir().join(format!("snip-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("nested"))?;
+        std::fs::write(root.join("a.txt"), b"alpha")?;
+        std::fs::write(root.join("nested").join("b.txt"), b"beta")?;
+        std::fs::write(root.join("ignore.bin"), b"\x00\x01\x02")?;
+
+        let entries = add_attachments_from_dir(&conn, snip_uuid, &root, Some(&["txt"]))?;
+        std::fs::remove_dir_all(&root)?;
+
+        let added: Vec<&DirImportEntry> = entries
+            .iter()
+            .filter(|e| matches!(e.outcome, DirImportOutcome::Added))
+            .collect();
+        let skipped: Vec<&DirImportEntry> = entries
+            .iter()
+            .filter(|e| matches!(e.outcome, DirImportOutcome::Skipped))
+            .collect();
+
+        assert_eq!(added.len(), 2);
+        assert_eq!(skipped.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_attachments_for_snip() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        let snip_uuid = Uuid::try_parse(ID_STR)?;
+
+        let dest_dir = std::env::temp_dir().join(format!("snip-export-{}", Uuid::new_v4()));
+        let written = export_attachments_for_snip(&conn, snip_uuid, &dest_dir)?;
+
+        assert!(!written.is_empty());
+        for path in &written {
+            assert!(path.exists());
+        }
+        std::fs::remove_dir_all(&dest_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_text_plaintext() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        let snip_uuid = Uuid::try_parse(ID_STR)?;
+
+        let root = std::env::temp_dir().join(format!("snip-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&root)?;
+        let path = root.join("notes.txt");
+        std::fs::write(&path, b"lorem ipsum dolor sit amet")?;
+
+        add_attachment(&conn, snip_uuid, &path)?;
+        std::fs::remove_dir_all(&root)?;
+
+        let attachments = get_attachment_all(&conn)?;
+        let id = *attachments.last().expect("at least one attachment");
+        let a = get_attachment_from_uuid(&conn, id)?;
+
+        let text = a.extract_text()?;
+        assert_eq!(text, Some("lorem ipsum dolor sit amet".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_attachments_by_mime() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+
+        let snip_uuid = Uuid::try_parse(ID_STR)?;
+        add_attachment(&conn, snip_uuid, Path::new("test_data/attachments/udhr.pdf"))?;
+
+        let pdfs = get_attachments_by_mime(&conn, "application/pdf")?;
+        assert!(!pdfs.is_empty());
+
+        let images = get_attachments_by_mime(&conn, "image/*")?;
+        assert!(images.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_source_mtime_is_captured_on_attach() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+
+        let snip_uuid = Uuid::try_parse(ID_STR)?;
+        let path = Path::new("test_data/attachments/udhr.pdf");
+        add_attachment(&conn, snip_uuid, path)?;
+
+        let attachments = get_attachment_all(&conn)?;
+        let id = *attachments.last().expect("at least one attachment");
+        let a = get_attachment_from_uuid(&conn, id)?;
+
+        assert!(a.source_mtime.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_attachment_streaming() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+
+        let snip_uuid = Uuid::try_parse(ID_STR)?;
+        let path = Path::new("test_data/attachments/udhr.pdf");
+        add_attachment(&conn, snip_uuid, path)?;
+
+        let attachments = get_attachment_all(&conn)?;
+        let id = *attachments.last().expect("at least one attachment");
+
+        let dest_dir = std::env::temp_dir().join(format!("snip-export-streaming-{}", Uuid::new_v4()));
+        let written = export_attachment_streaming(&conn, id, &dest_dir)?;
+
+        assert!(written.exists());
+        let original = std::fs::read(path)?;
+        let exported = std::fs::read(&written)?;
+        assert_eq!(exported, original);
+
+        std::fs::remove_dir_all(&dest_dir)?;
+        Ok(())
+    }
+
     #[test]
     fn test_get_attachment_from_uuid() -> Result<(), Box<dyn Error>> {
         let conn = prepare_database().expect("preparing in-memory database");
@@ -0,0 +1,337 @@
+use crate::snip::search::Operation;
+use crate::snip::search_uuids_matching_term;
+use roaring::RoaringBitmap;
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::HashSet;
+use std::error::Error;
+use uuid::Uuid;
+
+/// Returns the stable integer ordinal assigned to `uuid`, assigning the next free one
+/// (one past the current maximum) if this is the first time it's been seen. Ordinals are
+/// what the roaring bitmaps in `snip_term_bitmap` actually store, since a bitmap can only
+/// compactly represent integers, not uuids.
+pub fn ordinal_for(conn: &Connection, uuid: &Uuid) -> Result<u32, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT ordinal FROM snip_ordinal WHERE uuid = :uuid")?;
+    let existing: Option<i64> = stmt
+        .query_row(&[(":uuid", &uuid.to_string())], |row| row.get(0))
+        .optional()?;
+    if let Some(ordinal) = existing {
+        return Ok(ordinal as u32);
+    }
+
+    let mut stmt = conn.prepare("SELECT COALESCE(MAX(ordinal), -1) + 1 FROM snip_ordinal")?;
+    let next: i64 = stmt.query_row([], |row| row.get(0))?;
+
+    conn.execute(
+        "INSERT INTO snip_ordinal(uuid, ordinal) VALUES (:uuid, :ordinal)",
+        rusqlite::named_params! { ":uuid": uuid.to_string(), ":ordinal": next },
+    )?;
+    Ok(next as u32)
+}
+
+/// Resolves an ordinal back to the uuid it was assigned to. Public so callers that
+/// already hold a `RoaringBitmap` of candidate ordinals (such as `search_structured`'s
+/// bitmap-backed candidate phase) can translate its members back to uuids without going
+/// through `search_boolean`.
+pub fn uuid_for_ordinal(conn: &Connection, ordinal: u32) -> Result<Uuid, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT uuid FROM snip_ordinal WHERE ordinal = :ordinal")?;
+    let id_str: String =
+        stmt.query_row(&[(":ordinal", &(ordinal as i64))], |row| row.get(0))?;
+    Ok(Uuid::try_parse(&id_str)?)
+}
+
+/// Reads `term`'s posting bitmap out of `snip_term_bitmap`, or an empty bitmap if the
+/// term has never been set. Public so `search_structured`'s include/exclude/uuid
+/// candidate phase can intersect, union, and subtract these directly as compressed
+/// bitmap ops instead of building and comparing `Vec<Uuid>`s.
+pub fn term_bitmap(conn: &Connection, term: &str) -> Result<RoaringBitmap, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT bitmap FROM snip_term_bitmap WHERE term = :term")?;
+    let bytes: Option<Vec<u8>> = stmt
+        .query_row(&[(":term", &term)], |row| row.get(0))
+        .optional()?;
+    match bytes {
+        Some(bytes) => Ok(RoaringBitmap::deserialize_from(&bytes[..])?),
+        None => Ok(RoaringBitmap::new()),
+    }
+}
+
+fn write_term_bitmap(conn: &Connection, term: &str, bitmap: &RoaringBitmap) -> Result<(), Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    bitmap.serialize_into(&mut bytes)?;
+    conn.execute(
+        "INSERT INTO snip_term_bitmap(term, bitmap) VALUES (:term, :bitmap)
+         ON CONFLICT(term) DO UPDATE SET bitmap = :bitmap",
+        rusqlite::named_params! { ":term": term, ":bitmap": bytes },
+    )?;
+    Ok(())
+}
+
+/// Sets `uuid`'s bit in `term`'s posting bitmap, assigning `uuid` an ordinal first if it
+/// doesn't already have one. Called once per distinct term a document indexes under, in
+/// addition to the existing `snip_index_rs` row `Snip::index` already writes.
+pub fn set_term_bit(conn: &Connection, term: &str, uuid: &Uuid) -> Result<(), Box<dyn Error>> {
+    let ordinal = ordinal_for(conn, uuid)?;
+    let mut bitmap = term_bitmap(conn, term)?;
+    bitmap.insert(ordinal);
+    write_term_bitmap(conn, term, &bitmap)
+}
+
+/// Clears `uuid`'s bit from `term`'s posting bitmap, without needing an ordinal to
+/// already exist (a uuid with no ordinal yet trivially has no bits set anywhere). Used
+/// by `Snip::index` to undo a document's old term bitmaps before reindexing it, since
+/// unlike `snip_index_rs` (which is simply deleted and rewritten) a bitmap has to be
+/// edited in place per term.
+pub fn clear_term_bit(conn: &Connection, term: &str, uuid: &Uuid) -> Result<(), Box<dyn Error>> {
+    let ordinal = ordinal_for(conn, uuid)?;
+    let mut bitmap = term_bitmap(conn, term)?;
+    bitmap.remove(ordinal);
+    write_term_bitmap(conn, term, &bitmap)
+}
+
+/// Rebuilds `snip_term_bitmap` from scratch out of the existing `snip_index_rs` term
+/// rows, for databases that were indexed before the bitmap index existed.
+pub fn backfill_term_bitmaps(conn: &Connection) -> Result<(), Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT term FROM snip_index_rs")?;
+    let terms: Vec<String> = stmt
+        .query_and_then([], |row| row.get::<_, String>(0))?
+        .flatten()
+        .collect();
+
+    for term in terms {
+        let uuids = search_uuids_matching_term(conn, &term)?;
+        let mut bitmap = RoaringBitmap::new();
+        for uuid in &uuids {
+            bitmap.insert(ordinal_for(conn, uuid)?);
+        }
+        write_term_bitmap(conn, &term, &bitmap)?;
+    }
+    Ok(())
+}
+
+/// Evaluates a parsed boolean query tree directly against the roaring bitmap index
+/// rather than the per-term SQL lookups `evaluate_operation` uses, so multi-term
+/// AND/OR/NOT queries resolve as one pass of bitmap `&`/`|`/`andnot` rather than a Rust
+/// set intersection per candidate. The bitmap index holds no positions, so `Phrase` and
+/// `Near` leaves degrade to an `And` of their term bitmaps (word order and gaps are not
+/// checked); `Prefix` leaves are resolved with a `LIKE` scan of `snip_index_rs` and
+/// folded in via their ordinals.
+pub fn search_boolean(conn: &Connection, expr: &Operation) -> Result<Vec<Uuid>, Box<dyn Error>> {
+    let bitmap = evaluate_bitmap(conn, expr)?;
+    let mut uuids = Vec::with_capacity(bitmap.len() as usize);
+    for ordinal in bitmap.iter() {
+        uuids.push(uuid_for_ordinal(conn, ordinal)?);
+    }
+    Ok(uuids)
+}
+
+fn evaluate_bitmap(conn: &Connection, expr: &Operation) -> Result<RoaringBitmap, Box<dyn Error>> {
+    match expr {
+        Operation::Query(term) => term_bitmap(conn, term),
+        Operation::Prefix(prefix) => uuids_to_bitmap(conn, &prefix_fallback_uuids(conn, prefix)?),
+        Operation::Phrase(terms) => {
+            // no positional information in a bitmap, so a phrase degrades to "contains
+            // every term" (the And case below handles the actual intersection)
+            evaluate_bitmap(conn, &Operation::And(terms.iter().cloned().map(Operation::Query).collect()))
+        }
+        Operation::Near(a, b, _) => {
+            // likewise, a bitmap has no positions to check the gap against, so NEAR
+            // degrades to "contains both terms somewhere"
+            evaluate_bitmap(
+                conn,
+                &Operation::And(vec![Operation::Query(a.clone()), Operation::Query(b.clone())]),
+            )
+        }
+        Operation::Not(inner) => {
+            // a bare Not has meaning relative to the whole corpus: everything except
+            // whatever the inner expression matches
+            let negated = evaluate_bitmap(conn, inner)?;
+            Ok(universe_bitmap(conn)? - negated)
+        }
+        Operation::And(operations) => {
+            let mut result: Option<RoaringBitmap> = None;
+            let mut negated = RoaringBitmap::new();
+            for op in operations {
+                if let Operation::Not(inner) = op {
+                    negated |= evaluate_bitmap(conn, inner)?;
+                    continue;
+                }
+                let bitmap = evaluate_bitmap(conn, op)?;
+                result = Some(match result {
+                    Some(acc) => acc & bitmap,
+                    None => bitmap,
+                });
+            }
+            let result = match result {
+                Some(acc) => acc - negated,
+                None => universe_bitmap(conn)? - negated,
+            };
+            Ok(result)
+        }
+        Operation::Or(operations) => {
+            let mut result = RoaringBitmap::new();
+            for op in operations {
+                result |= evaluate_bitmap(conn, op)?;
+            }
+            Ok(result)
+        }
+    }
+}
+
+fn prefix_fallback_uuids(conn: &Connection, prefix: &str) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT uuid FROM snip_index_rs WHERE term LIKE :pattern")?;
+    let pattern = format!("{}%", prefix);
+    let rows = stmt.query_and_then(&[(":pattern", &pattern)], |row| row.get::<_, String>(0))?;
+    let mut uuids = HashSet::new();
+    for row in rows.flatten() {
+        uuids.insert(Uuid::try_parse(&row)?);
+    }
+    Ok(uuids)
+}
+
+fn uuids_to_bitmap(conn: &Connection, uuids: &HashSet<Uuid>) -> Result<RoaringBitmap, Box<dyn Error>> {
+    let mut bitmap = RoaringBitmap::new();
+    for uuid in uuids {
+        bitmap.insert(ordinal_for(conn, uuid)?);
+    }
+    Ok(bitmap)
+}
+
+/// Every ordinal ever assigned, i.e. the full corpus. Used as the ceiling a bare or
+/// top-level `Not` subtracts from, since `evaluate_bitmap` otherwise only ever sees
+/// the documents a positive term touches.
+fn universe_bitmap(conn: &Connection) -> Result<RoaringBitmap, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT ordinal FROM snip_ordinal")?;
+    let rows = stmt.query_and_then([], |row| row.get::<_, u32>(0))?;
+    let mut bitmap = RoaringBitmap::new();
+    for row in rows.flatten() {
+        bitmap.insert(row);
+    }
+    Ok(bitmap)
+}
+
+/// Builds a posting bitmap the way `search_structured` resolves an `IndexFuzzy(max_distance)`
+/// term: the union of `term_bitmap` for every indexed term within `max_distance` edits of
+/// `term`, found via `fuzzy_term_matches`'s Levenshtein automaton.
+pub fn term_bitmap_fuzzy(
+    conn: &Connection,
+    term: &str,
+    max_distance: usize,
+) -> Result<RoaringBitmap, Box<dyn Error>> {
+    let mut bitmap = RoaringBitmap::new();
+    for derivation in crate::snip::search::fuzzy_term_matches(conn, term, max_distance)? {
+        bitmap |= term_bitmap(conn, &derivation.term)?;
+    }
+    Ok(bitmap)
+}
+
+/// Like `term_bitmap_fuzzy`, but for a trailing `terms_include` entry under
+/// `SearchQuery::prefix_distance`: the union of `term_bitmap` for every indexed term whose
+/// prefix comes within `max_distance` edits of `term`, via `fuzzy_prefix_matches`.
+pub fn term_bitmap_prefix(
+    conn: &Connection,
+    term: &str,
+    max_distance: usize,
+) -> Result<RoaringBitmap, Box<dyn Error>> {
+    let mut bitmap = RoaringBitmap::new();
+    for derivation in crate::snip::search::fuzzy_prefix_matches(conn, term, max_distance)? {
+        bitmap |= term_bitmap(conn, &derivation.term)?;
+    }
+    Ok(bitmap)
+}
+
+/// Builds a bitmap of `uuids`' ordinals, assigning a fresh ordinal to any uuid seen for
+/// the first time. Public so `search_structured`'s `SearchQuery::uuids` restriction can
+/// intersect against it directly as a bitmap `AND`.
+pub fn uuids_bitmap(conn: &Connection, uuids: &[Uuid]) -> Result<RoaringBitmap, Box<dyn Error>> {
+    let mut bitmap = RoaringBitmap::new();
+    for uuid in uuids {
+        bitmap.insert(ordinal_for(conn, uuid)?);
+    }
+    Ok(bitmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snip;
+    use crate::snip::search::parse_query;
+    use crate::snip::test_prep::*;
+
+    #[test]
+    fn test_backfill_and_search_boolean_matches_evaluate_operation() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+        backfill_term_bitmaps(&conn)?;
+
+        let op = parse_query("lorem ipsum");
+        let via_bitmap: HashSet<Uuid> = search_boolean(&conn, &op)?.into_iter().collect();
+        let via_sets = crate::snip::search::evaluate_operation(&conn, &op)?;
+        assert_eq!(via_bitmap, via_sets);
+        assert!(!via_bitmap.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_boolean_excludes_negated_term_regardless_of_operand_order() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+        backfill_term_bitmaps(&conn)?;
+
+        // "lorem ipsum" is indexed under both terms, so a negated leading operand must
+        // exclude it just as surely as a trailing one would
+        let leading_not = parse_query("-lorem ipsum");
+        assert!(search_boolean(&conn, &leading_not)?.is_empty());
+
+        let trailing_not = parse_query("ipsum -lorem");
+        assert!(search_boolean(&conn, &trailing_not)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_boolean_resolves_standalone_not_against_full_corpus() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+        backfill_term_bitmaps(&conn)?;
+
+        // a bare `-term` collapses to a lone `Operation::Not`, which must resolve to
+        // "every document except those matching term" instead of erroring
+        let op = parse_query("-lorem");
+        let via_bitmap: HashSet<Uuid> = search_boolean(&conn, &op)?.into_iter().collect();
+        assert!(!via_bitmap.contains(&Uuid::try_parse(ID_STR)?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_term_bitmap_fuzzy_matches_typo_derivations() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        // "lorm" is one edit away from the indexed "lorem"; an exact lookup finds
+        // nothing, while the fuzzy bitmap must union in "lorem"'s postings
+        assert!(term_bitmap(&conn, "lorm")?.is_empty());
+        assert_eq!(
+            term_bitmap_fuzzy(&conn, "lorm", 1)?,
+            term_bitmap(&conn, "lorem")?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_term_bitmap_prefix_matches_still_being_typed_term() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        // "lor" is a prefix of the indexed term "lorem" but isn't itself indexed
+        assert!(term_bitmap(&conn, "lor")?.is_empty());
+        assert_eq!(
+            term_bitmap_prefix(&conn, "lor", 0)?,
+            term_bitmap(&conn, "lorem")?
+        );
+
+        Ok(())
+    }
+}
@@ -1,11 +1,14 @@
 use crate::snip;
 use chrono::{DateTime, FixedOffset};
 use rusqlite::Connection;
-use rust_stemmers::Stemmer;
+use rusqlite::OptionalExtension;
 use std::collections::HashMap;
 use std::error::Error;
 use std::io;
 use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
 use unicode_segmentation::UnicodeSegmentation;
 use uuid::Uuid;
 
@@ -32,9 +35,35 @@ impl Snip {
 
     /// Removes all word indices for a document
     fn drop_word_indices(&self, conn: &Connection) -> Result<(), Box<dyn Error>> {
-        let mut stmt = conn.prepare("DELETE FROM snip_index_rs WHERE uuid = :uuid")?;
-        stmt.execute(&[(":uuid", &self.uuid.to_string())])?;
-        Ok(())
+        drop_word_indices_for_uuid(conn, &self.uuid)
+    }
+
+    /// Indexes this document's own text together with any extractable attachment text,
+    /// so a search for a term that only appears inside an attachment's content still
+    /// finds this document. Delegates to the existing `analyze`/`index` pipeline by
+    /// temporarily appending the extracted text; `self.text` itself is left unchanged.
+    pub fn index_with_attachments(&mut self, conn: &Connection) -> Result<(), Box<dyn Error>> {
+        self.collect_attachments(conn)?;
+
+        let mut extracted = String::new();
+        for attachment in &self.attachments {
+            if let Some(text) = attachment.extract_text()? {
+                extracted.push(' ');
+                extracted.push_str(&text);
+            }
+        }
+
+        if extracted.is_empty() {
+            return self.index(conn);
+        }
+
+        let original_text = self.text.clone();
+        self.text.push_str(&extracted);
+        self.analysis.words.clear();
+        let result = self.index(conn);
+        self.text = original_text;
+        self.analysis.words.clear();
+        result
     }
 
     /// Collects all attachments belonging to this document
@@ -68,7 +97,6 @@ impl Snip {
         let mut stmt = conn.prepare(
             "SELECT count, positions FROM snip_index_rs WHERE uuid = :uuid AND term = :term",
         )?;
-        let mut counter: usize = 0;
         let mut rows = stmt.query_map(
             &[(":uuid", &self.uuid.to_string()), (":term", term)],
             |row| {
@@ -78,8 +106,6 @@ impl Snip {
                     .split(',')
                     .map(|x| x.parse::<u64>().expect("error parsing u64 from string"))
                     .collect();
-                println!("counter: {}", counter);
-                counter += 1;
 
                 Ok(WordIndex {
                     count,
@@ -107,40 +133,93 @@ impl Snip {
             self.analyze()?;
         }
 
-        // build counts of each term
-        let mut terms: HashMap<String, u64> = HashMap::new();
-        for word in &self.analysis.words {
-            let count = terms.entry(word.stem.to_owned()).or_insert(1);
-            *count += 1;
-        }
-        // println!("{:#?}", terms);
+        self.drop_word_indices(conn)?;
+        self.write_field_index(conn, "body")?;
+
+        // index the document's name under its own field tag, separately from its body,
+        // so a name match can be weighted above a body match during BM25 scoring.
+        // Re-runs the same stem/stop-word pipeline against `self.name` by temporarily
+        // swapping it in for `self.text`, then restores the body's analysis.
+        let original_text = self.text.clone();
+        let original_words = std::mem::take(&mut self.analysis.words);
+        self.text = self.name.clone();
+        self.analyze()?;
+        self.write_field_index(conn, "name")?;
+        self.text = original_text;
+        self.analysis.words = original_words;
+
+        self.write_doc_len(conn)?;
 
+        Ok(())
+    }
+
+    /// Caches this document's total indexed word count (across both its `body` and
+    /// `name` fields) in `snip_doc_len`, so BM25 scoring can read a document's length
+    /// without re-summing `snip_index_rs` on every query.
+    fn write_doc_len(&self, conn: &Connection) -> Result<(), Box<dyn Error>> {
+        let mut stmt =
+            conn.prepare("SELECT COALESCE(SUM(count), 0) FROM snip_index_rs WHERE uuid = :uuid")?;
+        let length: i64 = stmt.query_row(&[(":uuid", &self.uuid.to_string())], |row| row.get(0))?;
+
+        conn.execute(
+            "INSERT INTO snip_doc_len(uuid, length) VALUES (:uuid, :length)
+             ON CONFLICT(uuid) DO UPDATE SET length = :length",
+            rusqlite::named_params! { ":uuid": self.uuid.to_string(), ":length": length },
+        )?;
+        Ok(())
+    }
+
+    /// Writes a `WordIndex` row per distinct (stemmed, non-stop-word) term currently in
+    /// `self.analysis.words`, tagged with `field` (`"body"` or `"name"`).
+    fn write_field_index(&mut self, conn: &Connection, field: &str) -> Result<(), Box<dyn Error>> {
         // collect the positions of each term in the document
         let mut terms_positions: HashMap<String, Vec<u64>> = HashMap::new();
         for (pos, word) in self.analysis.words.iter().enumerate() {
+            if snip::is_stop_word(&word.stem) {
+                continue;
+            }
             let positions = terms_positions
                 .entry(word.stem.clone())
                 .or_insert(Vec::new());
             positions.push(pos as u64);
         }
-        // println!("{:#?}", terms_positions);
 
-        self.drop_word_indices(conn)?;
         for pos in terms_positions {
-            // insert this data
             // term: lorem count: 2 positions: "0,217"
-            // println!("term: {} count: {} positions: {:#?}", pos.0, pos.1.len(), pos_joined);
+            let term = pos.0;
             let index = WordIndex {
                 count: pos.1.len() as u64,
                 positions: pos.1,
-                term: pos.0,
+                term: term.clone(),
             };
-            self.write_word_index(conn, index)?;
+            let count = index.count;
+            self.write_word_index(conn, index, field)?;
+            snip::set_term_bit(conn, &term, &self.uuid)?;
+            snip::refresh_term_summary(conn, &term)?;
+            self.write_prefix_index(conn, &term, count)?;
         }
 
         Ok(())
     }
 
+    /// Writes one `snip_prefix_index_rs` row per grapheme-prefix of `term` (length
+    /// `1..=term`'s full grapheme count), accumulating `count` into any row already
+    /// written for the same (prefix, document) pair — a term indexed under both the
+    /// `body` and `name` fields contributes to the same prefix rows rather than
+    /// overwriting them.
+    fn write_prefix_index(&self, conn: &Connection, term: &str, count: u64) -> Result<(), Box<dyn Error>> {
+        let graphemes: Vec<&str> = term.graphemes(true).collect();
+        for prefix_len in 1..=graphemes.len() {
+            let prefix: String = graphemes[..prefix_len].concat();
+            conn.execute(
+                "INSERT INTO snip_prefix_index_rs(prefix, uuid, count) VALUES (:prefix, :uuid, :count)
+                 ON CONFLICT(prefix, uuid) DO UPDATE SET count = count + :count",
+                rusqlite::named_params! { ":prefix": prefix, ":uuid": self.uuid.to_string(), ":count": count },
+            )?;
+        }
+        Ok(())
+    }
+
     /// scans and assigns all prefix and suffix strings to all analyzed words
     pub fn scan_fragments(&mut self) -> Result<(), SnipError> {
         // scan the document for tokens, in order collecting surrounding data for each token
@@ -232,26 +311,24 @@ impl Snip {
         Ok(())
     }
 
-    /// Stems the document words and writes the stems to the analysis.
+    /// Stems the document words and writes the stems to the analysis, using whichever
+    /// `Analyzer` is currently active (see `snip::set_analyzer`/`set_stemmer_language`).
     fn stem_words(&mut self) -> Result<(), SnipError> {
-        let stemmer = Stemmer::create(rust_stemmers::Algorithm::English);
-
         for word_analyzed in self.analysis.words.iter_mut() {
-            let word_tmp = word_analyzed.word.to_lowercase().clone();
-
-            // Most stemmers require apostrophe in ASCII for compatibility. While we
-            // make the transformation here so that stems are generated correctly, we
-            // want to avoid changing the original data.
-            let word_tmp = word_tmp.replace('â€™', "'");
-
-            let stem = stemmer.stem(word_tmp.as_str());
-            word_analyzed.stem = stem.to_string();
+            word_analyzed.stem = snip::stem_word(&word_analyzed.word);
         }
         Ok(())
     }
 
-    /// Writes all fields to the database, overwriting existing data
-    pub fn update(&self, conn: &Connection) -> Result<(), Box<dyn Error>> {
+    /// Writes all fields to the database, overwriting existing data. If `queue` is
+    /// given, the changed content is reindexed off the write path via
+    /// `IndexTask::Update` rather than left for the caller to reindex inline with
+    /// `index()`.
+    pub fn update(
+        &self,
+        conn: &Connection,
+        queue: Option<&IndexQueue>,
+    ) -> Result<(), Box<dyn Error>> {
         let mut stmt = conn.prepare("UPDATE snip SET (data, timestamp, name) = (:data, :timestamp, :name) WHERE uuid = :uuid")?;
         let _ = stmt.execute(&[
             (":data", &self.text.to_string()),
@@ -259,16 +336,22 @@ impl Snip {
             (":name", &self.name.to_string()),
             (":uuid", &self.uuid.to_string()),
         ])?;
+
+        if let Some(queue) = queue {
+            queue.enqueue(IndexTask::Update(self.uuid))?;
+        }
         Ok(())
     }
 
-    /// Writes an index for a word to the database for searching
+    /// Writes an index for a word to the database for searching, tagged with `field`
+    /// (`"body"` or `"name"`) so scoring can weight a name match above a body match.
     fn write_word_index(
         &mut self,
         conn: &Connection,
         word: WordIndex,
+        field: &str,
     ) -> Result<(), Box<dyn Error>> {
-        let mut stmt = conn.prepare("INSERT OR REPLACE INTO snip_index_rs(term, uuid, count, positions) VALUES (:term, :uuid, :count, :positions)")?;
+        let mut stmt = conn.prepare("INSERT OR REPLACE INTO snip_index_rs(term, uuid, count, positions, field) VALUES (:term, :uuid, :count, :positions, :field)")?;
         let positions_string = word.positions_to_string();
         let count = word.count;
         let result = stmt.execute(&[
@@ -276,6 +359,7 @@ impl Snip {
             (":uuid", &self.uuid.to_string()),
             (":count", &count.to_string()),
             (":positions", &positions_string),
+            (":field", &field.to_string()),
         ])?;
 
         if result != 1 {
@@ -287,6 +371,39 @@ impl Snip {
     }
 }
 
+/// Removes all word indices for `uuid`, independent of any in-memory `Snip`. Used by
+/// `Snip::drop_word_indices` and, importantly, by a queued `IndexTask::Remove`, which
+/// runs after the `snip` row itself may already be gone and so cannot rely on
+/// `get_from_uuid` to rebuild a `Snip` first.
+fn drop_word_indices_for_uuid(conn: &Connection, uuid: &Uuid) -> Result<(), Box<dyn Error>> {
+    // a bitmap has to be edited bit-by-bit rather than dropped wholesale, so clear this
+    // document's bit from every term bitmap it's currently set in before the
+    // snip_index_rs rows driving that membership are deleted
+    let mut stmt = conn.prepare("SELECT DISTINCT term FROM snip_index_rs WHERE uuid = :uuid")?;
+    let terms: Vec<String> = stmt
+        .query_and_then(&[(":uuid", &uuid.to_string())], |row| {
+            row.get::<_, String>(0)
+        })?
+        .flatten()
+        .collect();
+    for term in &terms {
+        snip::clear_term_bit(conn, term, uuid)?;
+    }
+
+    let mut stmt = conn.prepare("DELETE FROM snip_index_rs WHERE uuid = :uuid")?;
+    stmt.execute(&[(":uuid", &uuid.to_string())])?;
+
+    let mut stmt = conn.prepare("DELETE FROM snip_doc_len WHERE uuid = :uuid")?;
+    stmt.execute(&[(":uuid", &uuid.to_string())])?;
+
+    // this document no longer contributes to these terms' corpus-wide stats
+    for term in &terms {
+        snip::refresh_term_summary(conn, term)?;
+    }
+
+    Ok(())
+}
+
 /// Clear the search index
 pub fn clear_index(conn: &Connection) -> Result<usize, Box<dyn Error>> {
     let mut stmt = conn.prepare("DELETE FROM snip_index_rs")?;
@@ -301,13 +418,114 @@ pub fn create_snip_tables(conn: &Connection) -> Result<(), Box<dyn Error>> {
     )?;
     stmt.raw_execute()?;
 
-    let mut stmt = conn.prepare("CREATE TABLE IF NOT EXISTS snip_attachment(uuid TEXT, snip_uuid TEXT, timestamp TEXT, name TEXT, data BLOB, size INTEGER)")?;
+    let mut stmt = conn.prepare("CREATE TABLE IF NOT EXISTS snip_attachment(uuid TEXT, snip_uuid TEXT, timestamp TEXT, name TEXT, data BLOB, size INTEGER, hash TEXT, data_ref INTEGER, media_type TEXT, dim_x INTEGER, dim_y INTEGER, akey BLOB, source_mtime TEXT, key_id TEXT)")?;
     stmt.raw_execute()?;
 
     let mut stmt = conn.prepare("CREATE TABLE IF NOT EXISTS snip_index(term TEXT, uuid TEXT, count INTEGER, positions TEXT)")?;
     stmt.raw_execute()?;
 
-    let mut stmt = conn.prepare("CREATE TABLE IF NOT EXISTS snip_index_rs(term TEXT, uuid TEXT, count INTEGER, positions TEXT)")?;
+    let mut stmt = conn.prepare("CREATE TABLE IF NOT EXISTS snip_index_rs(term TEXT, uuid TEXT, count INTEGER, positions TEXT, field TEXT NOT NULL DEFAULT 'body')")?;
+    stmt.raw_execute()?;
+
+    let mut stmt = conn.prepare("CREATE TABLE IF NOT EXISTS snip_synonym(term TEXT, synonym TEXT)")?;
+    stmt.raw_execute()?;
+
+    // caches each document's total indexed word count so BM25 scoring can read it
+    // directly instead of re-summing snip_index_rs on every query
+    let mut stmt = conn.prepare(
+        "CREATE TABLE IF NOT EXISTS snip_doc_len(uuid TEXT PRIMARY KEY, length INTEGER NOT NULL)",
+    )?;
+    stmt.raw_execute()?;
+
+    // a stable integer ordinal per document, since a roaring bitmap can only represent
+    // integers; snip_term_bitmap's postings are sets of these ordinals, not uuids
+    let mut stmt = conn.prepare(
+        "CREATE TABLE IF NOT EXISTS snip_ordinal(uuid TEXT PRIMARY KEY, ordinal INTEGER UNIQUE)",
+    )?;
+    stmt.raw_execute()?;
+
+    // compressed term -> document-ordinal-set postings, evaluated directly with bitmap
+    // and/or/andnot for boolean queries instead of per-term SQL lookups
+    let mut stmt = conn
+        .prepare("CREATE TABLE IF NOT EXISTS snip_term_bitmap(term TEXT PRIMARY KEY, bitmap BLOB)")?;
+    stmt.raw_execute()?;
+
+    // per-term document frequency and total occurrence count, maintained incrementally
+    // as documents are indexed/removed so stats_index doesn't have to rescan
+    // snip_index_rs in full on every call
+    let mut stmt = conn.prepare(
+        "CREATE TABLE IF NOT EXISTS snip_term_summary(\
+            term TEXT PRIMARY KEY, \
+            document_frequency INTEGER NOT NULL, \
+            total_count INTEGER NOT NULL\
+        )",
+    )?;
+    stmt.raw_execute()?;
+
+    // per-document occurrence counts for every grapheme-prefix of every indexed stem, so
+    // a trailing partial query word can be expanded to candidate documents without a
+    // `LIKE` scan of snip_index_rs; index_all_items prunes prefixes too rare to be worth
+    // keeping (see prune_rare_prefixes)
+    let mut stmt = conn.prepare(
+        "CREATE TABLE IF NOT EXISTS snip_prefix_index_rs(\
+            prefix TEXT, \
+            uuid TEXT, \
+            count INTEGER\
+        )",
+    )?;
+    stmt.raw_execute()?;
+
+    // durable background indexing work, so a crashed worker can resume where it left
+    // off and CLI callers can poll a task's progress by id
+    let mut stmt = conn.prepare(
+        "CREATE TABLE IF NOT EXISTS snip_task(\
+            id INTEGER PRIMARY KEY AUTOINCREMENT, \
+            kind TEXT NOT NULL, \
+            uuid TEXT, \
+            state TEXT NOT NULL, \
+            error TEXT, \
+            created_at TEXT NOT NULL, \
+            updated_at TEXT NOT NULL\
+        )",
+    )?;
+    stmt.raw_execute()?;
+
+    // migrate databases created before these columns were introduced; ignore
+    // "duplicate column" errors from rusqlite on already-migrated databases
+    let _ = conn.execute("ALTER TABLE snip_attachment ADD COLUMN hash TEXT", []);
+    let _ = conn.execute("ALTER TABLE snip_attachment ADD COLUMN data_ref INTEGER", []);
+    let _ = conn.execute("ALTER TABLE snip_attachment ADD COLUMN media_type TEXT", []);
+    let _ = conn.execute("ALTER TABLE snip_attachment ADD COLUMN dim_x INTEGER", []);
+    let _ = conn.execute("ALTER TABLE snip_attachment ADD COLUMN dim_y INTEGER", []);
+    let _ = conn.execute("ALTER TABLE snip_attachment ADD COLUMN akey BLOB", []);
+    let _ = conn.execute("ALTER TABLE snip_attachment ADD COLUMN source_mtime TEXT", []);
+    let _ = conn.execute("ALTER TABLE snip_attachment ADD COLUMN key_id TEXT", []);
+    let _ = conn.execute(
+        "ALTER TABLE snip_index_rs ADD COLUMN field TEXT NOT NULL DEFAULT 'body'",
+        [],
+    );
+
+    // a blob "owns" its data when data_ref is NULL; only owners need a unique hash
+    let mut stmt = conn.prepare("CREATE UNIQUE INDEX IF NOT EXISTS snip_attachment_hash_idx ON snip_attachment(hash) WHERE data_ref IS NULL")?;
+    stmt.raw_execute()?;
+
+    // speeds up the `term >= :p AND term < :p_upper` range scan prefix lookups rely on,
+    // alongside the exact-term lookups already on the hot path of every query
+    let mut stmt =
+        conn.prepare("CREATE INDEX IF NOT EXISTS snip_index_rs_term_idx ON snip_index_rs(term)")?;
+    stmt.raw_execute()?;
+
+    // a (term, synonym) pair is a single directional mapping; this keeps add_synonym
+    // idempotent rather than accumulating duplicate rows on repeated calls
+    let mut stmt = conn.prepare(
+        "CREATE UNIQUE INDEX IF NOT EXISTS snip_synonym_pair_idx ON snip_synonym(term, synonym)",
+    )?;
+    stmt.raw_execute()?;
+
+    // one row per (prefix, document); required for write_prefix_index's upsert
+    let mut stmt = conn.prepare(
+        "CREATE UNIQUE INDEX IF NOT EXISTS snip_prefix_index_rs_pair_idx ON snip_prefix_index_rs(prefix, uuid)",
+    )?;
     stmt.raw_execute()?;
 
     Ok(())
@@ -315,7 +533,48 @@ pub fn create_snip_tables(conn: &Connection) -> Result<(), Box<dyn Error>> {
 
 /// Create the table used to index documents for full text search. This is only done when the table is not present.
 pub fn create_index_table(conn: &Connection) -> Result<(), Box<dyn Error>> {
-    let mut stmt = conn.prepare("CREATE TABLE IF NOT EXISTS snip_index_rs(term TEXT, uuid TEXT, count INTEGER, positions TEXT)")?;
+    let mut stmt = conn.prepare("CREATE TABLE IF NOT EXISTS snip_index_rs(term TEXT, uuid TEXT, count INTEGER, positions TEXT, field TEXT NOT NULL DEFAULT 'body')")?;
+    stmt.raw_execute()?;
+
+    let mut stmt =
+        conn.prepare("CREATE INDEX IF NOT EXISTS snip_index_rs_term_idx ON snip_index_rs(term)")?;
+    stmt.raw_execute()?;
+
+    let mut stmt = conn.prepare(
+        "CREATE TABLE IF NOT EXISTS snip_doc_len(uuid TEXT PRIMARY KEY, length INTEGER NOT NULL)",
+    )?;
+    stmt.raw_execute()?;
+
+    let mut stmt = conn.prepare(
+        "CREATE TABLE IF NOT EXISTS snip_ordinal(uuid TEXT PRIMARY KEY, ordinal INTEGER UNIQUE)",
+    )?;
+    stmt.raw_execute()?;
+
+    let mut stmt = conn
+        .prepare("CREATE TABLE IF NOT EXISTS snip_term_bitmap(term TEXT PRIMARY KEY, bitmap BLOB)")?;
+    stmt.raw_execute()?;
+
+    let mut stmt = conn.prepare(
+        "CREATE TABLE IF NOT EXISTS snip_term_summary(\
+            term TEXT PRIMARY KEY, \
+            document_frequency INTEGER NOT NULL, \
+            total_count INTEGER NOT NULL\
+        )",
+    )?;
+    stmt.raw_execute()?;
+
+    let mut stmt = conn.prepare(
+        "CREATE TABLE IF NOT EXISTS snip_prefix_index_rs(\
+            prefix TEXT, \
+            uuid TEXT, \
+            count INTEGER\
+        )",
+    )?;
+    stmt.raw_execute()?;
+
+    let mut stmt = conn.prepare(
+        "CREATE UNIQUE INDEX IF NOT EXISTS snip_prefix_index_rs_pair_idx ON snip_prefix_index_rs(prefix, uuid)",
+    )?;
     stmt.raw_execute()?;
 
     Ok(())
@@ -345,6 +604,24 @@ pub fn find_by_graph(word: &str, text: Vec<&str>) -> Option<usize> {
     None
 }
 
+/// Reads `path` as raw bytes and decodes it lossily: any byte sequence that isn't
+/// valid UTF-8 becomes U+FFFD (the replacement character) rather than failing the read.
+/// Used by `add` under its `--lossy` flag so a malformed byte sequence no longer aborts
+/// the command the way `std::fs::read_to_string`'s hard UTF-8 requirement does. `import`
+/// and `update` do not call it and have no `--lossy` flag.
+pub fn read_file_lossy(path: &str) -> Result<String, Box<dyn Error>> {
+    let bytes = std::fs::read(path)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Reads stdin to EOF as raw bytes and decodes it lossily, the stdin counterpart to
+/// `read_file_lossy`.
+pub fn read_stdin_lossy() -> Result<String, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    io::stdin().read_to_end(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
 /// Generate document name from provided text
 pub fn generate_name(text: &String, count: usize) -> Result<String, Box<dyn Error>> {
     let mut name = String::new();
@@ -407,11 +684,393 @@ pub fn index_all_items(conn: &Connection) -> Result<(), Box<dyn Error>> {
         }
         s.index(conn)?;
     }
+
+    prune_rare_prefixes(conn)?;
+    Ok(())
+}
+
+/// A prefix must complete to more than this many distinct corpus terms to be worth
+/// keeping in `snip_prefix_index_rs`; below it the prefix is nearly unique to a single
+/// term, so `get_by_prefix` falls back to scanning `snip_index_rs` directly for it
+/// rather than paying to index every rare prefix up front.
+pub const PREFIX_INDEX_MIN_TERMS: u64 = 3;
+
+/// Deletes every `snip_prefix_index_rs` row for a prefix that completes to
+/// `PREFIX_INDEX_MIN_TERMS` or fewer distinct terms across the whole corpus, run once at
+/// the end of a full reindex rather than per-document, since "distinct terms for this
+/// prefix" is a corpus-wide property that only stabilizes once every document has been
+/// (re)written.
+fn prune_rare_prefixes(conn: &Connection) -> Result<(), Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT prefix FROM snip_prefix_index_rs")?;
+    let prefixes: Vec<String> = stmt
+        .query_and_then([], |row| row.get::<_, String>(0))?
+        .flatten()
+        .collect();
+
+    for prefix in prefixes {
+        let mut stmt =
+            conn.prepare("SELECT COUNT(DISTINCT term) FROM snip_index_rs WHERE term LIKE :pattern")?;
+        let pattern = format!("{}%", prefix);
+        let distinct_terms: i64 =
+            stmt.query_row(&[(":pattern", &pattern)], |row| row.get(0))?;
+
+        if (distinct_terms as u64) <= PREFIX_INDEX_MIN_TERMS {
+            conn.execute(
+                "DELETE FROM snip_prefix_index_rs WHERE prefix = :prefix",
+                &[(":prefix", &prefix)],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the active stop-word set and rebuilds the index for every document against
+/// it, since `snip::set_stop_words` alone only affects documents indexed afterward.
+pub fn set_stop_words_and_reindex(
+    conn: &Connection,
+    words: std::collections::HashSet<String>,
+) -> Result<(), Box<dyn Error>> {
+    snip::set_stop_words(words);
+    index_all_items(conn)
+}
+
+/// A unit of indexing work to be performed off the write path by an `IndexQueue`
+/// worker thread.
+#[derive(Debug, Clone, Copy)]
+pub enum IndexTask {
+    Add(Uuid),
+    Update(Uuid),
+    Remove(Uuid),
+}
+
+fn index_task_uuid(task: &IndexTask) -> Uuid {
+    match task {
+        IndexTask::Add(id) | IndexTask::Update(id) | IndexTask::Remove(id) => *id,
+    }
+}
+
+fn apply_index_task(conn: &Connection, task: &IndexTask) -> Result<(), Box<dyn Error>> {
+    match task {
+        IndexTask::Add(id) | IndexTask::Update(id) => {
+            let mut s = get_from_uuid(conn, id)?;
+            s.analyze()?;
+            s.index(conn)?;
+            Ok(())
+        }
+        // the snip row itself is typically already gone by the time this task is
+        // drained, so this must not route through get_from_uuid
+        IndexTask::Remove(id) => drop_word_indices_for_uuid(conn, id),
+    }
+}
+
+enum IndexMessage {
+    Task(IndexTask),
+    Flush(Sender<()>),
+}
+
+/// Decouples tokenization/indexing from the write path: `insert_snip`/`update`/
+/// `remove_snip` can enqueue a task here instead of rebuilding the index inline, and a
+/// worker thread drains the queue on its own connection to the same database so bulk
+/// operations stay fast while the index becomes eventually consistent. Repeated tasks
+/// queued for the same uuid before the next flush are coalesced, keeping only the most
+/// recent one.
+pub struct IndexQueue {
+    sender: Sender<IndexMessage>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl IndexQueue {
+    /// Spawns the worker thread, which opens its own connection to `db_path`.
+    pub fn spawn(db_path: PathBuf) -> Self {
+        let (sender, receiver): (Sender<IndexMessage>, Receiver<IndexMessage>) = mpsc::channel();
+
+        let worker = thread::spawn(move || {
+            let conn = match Connection::open(&db_path) {
+                Ok(conn) => conn,
+                Err(e) => {
+                    eprintln!("index queue worker failed to open database: {}", e);
+                    return;
+                }
+            };
+
+            let mut pending: Vec<IndexTask> = Vec::new();
+            for message in receiver {
+                match message {
+                    IndexMessage::Task(task) => {
+                        let uuid = index_task_uuid(&task);
+                        pending.retain(|t| index_task_uuid(t) != uuid);
+                        pending.push(task);
+                    }
+                    IndexMessage::Flush(ack) => {
+                        for task in pending.drain(..) {
+                            if let Err(e) = apply_index_task(&conn, &task) {
+                                eprintln!("index queue worker failed to apply task: {}", e);
+                            }
+                        }
+                        let _ = ack.send(());
+                    }
+                }
+            }
+        });
+
+        IndexQueue {
+            sender,
+            worker: Some(worker),
+        }
+    }
+
+    /// Enqueues a task to be applied the next time the queue is flushed.
+    pub fn enqueue(&self, task: IndexTask) -> Result<(), Box<dyn Error>> {
+        self.sender
+            .send(IndexMessage::Task(task))
+            .map_err(|e| Box::new(SnipError::General(e.to_string())) as Box<dyn Error>)
+    }
+
+    /// Blocks until every task enqueued so far has been applied.
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        let (ack_tx, ack_rx) = mpsc::channel();
+        self.sender
+            .send(IndexMessage::Flush(ack_tx))
+            .map_err(|e| Box::new(SnipError::General(e.to_string())) as Box<dyn Error>)?;
+        ack_rx
+            .recv()
+            .map_err(|e| Box::new(SnipError::General(e.to_string())) as Box<dyn Error>)
+    }
+
+    /// Alias for `flush`, matching the "drain the queue" naming used to describe this
+    /// subsystem.
+    pub fn drain(&self) -> Result<(), Box<dyn Error>> {
+        self.flush()
+    }
+}
+
+impl Drop for IndexQueue {
+    fn drop(&mut self) {
+        // dropping `sender` closes the channel, which ends the worker's for-loop; it has
+        // to be dropped here, before the join, since fields are only dropped after this
+        let (closed, _) = mpsc::channel();
+        drop(std::mem::replace(&mut self.sender, closed));
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// The lifecycle state of a `snip_task` row, as persisted in its `state` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Enqueued,
+    Processing,
+    Succeeded,
+    Failed,
+}
+
+impl TaskState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TaskState::Enqueued => "enqueued",
+            TaskState::Processing => "processing",
+            TaskState::Succeeded => "succeeded",
+            TaskState::Failed => "failed",
+        }
+    }
+
+    fn from_str(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s {
+            "enqueued" => Ok(TaskState::Enqueued),
+            "processing" => Ok(TaskState::Processing),
+            "succeeded" => Ok(TaskState::Succeeded),
+            "failed" => Ok(TaskState::Failed),
+            other => Err(Box::new(SnipError::General(format!(
+                "unrecognized task state: {}",
+                other
+            )))),
+        }
+    }
+}
+
+/// A row read back from `snip_task`, reporting where a background indexing task stands.
+#[derive(Debug, Clone)]
+pub struct TaskStatus {
+    pub id: i64,
+    pub kind: String,
+    pub uuid: Option<Uuid>,
+    pub state: TaskState,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// Persists a `kind` ("index", "reindex", or "remove") task for `uuid` (absent for a
+/// whole-corpus reindex) into `snip_task` with state `enqueued`, returning its row id so
+/// the caller can later poll it with `task_status`. Unlike `IndexQueue`, which only
+/// tracks work in memory for the lifetime of the process, a `snip_task` row survives a
+/// crash, which is what lets `recover_interrupted_tasks` re-pick work a worker died
+/// partway through.
+///
+/// This is a standalone, opt-in API: nothing in `insert_snip`/`Snip::update`/
+/// `remove_snip` enqueues here, and nothing drains `snip_task` automatically. A caller
+/// that wants durable, poll-able indexing instead of the in-process `IndexQueue` used by
+/// the write path enqueues with this (or `enqueue_index`/`enqueue_remove`/
+/// `enqueue_reindex_all`) and drains with `drain_tasks` itself, e.g. from a maintenance
+/// job or a daemon that can tolerate the extra write per task.
+fn enqueue_task(conn: &Connection, kind: &str, uuid: Option<Uuid>) -> Result<i64, Box<dyn Error>> {
+    let now = chrono::Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO snip_task(kind, uuid, state, error, created_at, updated_at) \
+         VALUES (:kind, :uuid, :state, NULL, :now, :now)",
+        rusqlite::named_params! {
+            ":kind": kind,
+            ":uuid": uuid.map(|u| u.to_string()),
+            ":state": TaskState::Enqueued.as_str(),
+            ":now": now,
+        },
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Enqueues a durable task to index (or reindex, if already present) `uuid`.
+pub fn enqueue_index(conn: &Connection, uuid: Uuid) -> Result<i64, Box<dyn Error>> {
+    enqueue_task(conn, "index", Some(uuid))
+}
+
+/// Enqueues a durable task to remove `uuid`'s word index.
+pub fn enqueue_remove(conn: &Connection, uuid: Uuid) -> Result<i64, Box<dyn Error>> {
+    enqueue_task(conn, "remove", Some(uuid))
+}
+
+/// Enqueues a durable task to reindex every document in the corpus.
+pub fn enqueue_reindex_all(conn: &Connection) -> Result<i64, Box<dyn Error>> {
+    enqueue_task(conn, "reindex", None)
+}
+
+/// Reads back the current state of a previously enqueued task, for a caller polling the
+/// progress of a batch it fired off with `enqueue_index`/`enqueue_remove`.
+pub fn task_status(conn: &Connection, task_id: i64) -> Result<Option<TaskStatus>, Box<dyn Error>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, kind, uuid, state, error, created_at, updated_at FROM snip_task WHERE id = :id",
+    )?;
+    stmt.query_row(&[(":id", &task_id)], |row| {
+        let uuid: Option<String> = row.get(2)?;
+        let state: String = row.get(3)?;
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            uuid,
+            state,
+            row.get::<_, Option<String>>(4)?,
+            row.get::<_, String>(5)?,
+            row.get::<_, String>(6)?,
+        ))
+    })
+    .optional()?
+    .map(|(id, kind, uuid, state, error, created_at, updated_at)| {
+        Ok(TaskStatus {
+            id,
+            kind,
+            uuid: uuid.map(|u| Uuid::try_parse(&u)).transpose()?,
+            state: TaskState::from_str(&state)?,
+            error,
+            created_at,
+            updated_at,
+        })
+    })
+    .transpose()
+}
+
+fn set_task_state(
+    conn: &Connection,
+    task_id: i64,
+    state: TaskState,
+    error: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    conn.execute(
+        "UPDATE snip_task SET state = :state, error = :error, updated_at = :now WHERE id = :id",
+        rusqlite::named_params! {
+            ":state": state.as_str(),
+            ":error": error,
+            ":now": chrono::Utc::now().to_rfc3339(),
+            ":id": task_id,
+        },
+    )?;
+    Ok(())
+}
+
+/// Resets any task left in `processing` back to `enqueued`, for a worker started after a
+/// previous run crashed or was killed mid-task. Called once by `drain_tasks` before it
+/// looks for new work, so a crash never silently drops a task that was in flight.
+fn recover_interrupted_tasks(conn: &Connection) -> Result<(), Box<dyn Error>> {
+    conn.execute(
+        "UPDATE snip_task SET state = :enqueued WHERE state = :processing",
+        rusqlite::named_params! {
+            ":enqueued": TaskState::Enqueued.as_str(),
+            ":processing": TaskState::Processing.as_str(),
+        },
+    )?;
     Ok(())
 }
 
-/// Adds a new document to the database
-pub fn insert_snip(conn: &Connection, s: &Snip) -> Result<(), Box<dyn Error>> {
+/// Applies every `enqueued` task in `snip_task` (first recovering any left in
+/// `processing` by a crashed prior run), marking each `processing` while it runs and
+/// `succeeded`/`failed` once it's done. Nothing calls this automatically; a caller using
+/// the `snip_task` API is responsible for invoking it, e.g. on a timer or before exit.
+pub fn drain_tasks(conn: &Connection) -> Result<(), Box<dyn Error>> {
+    recover_interrupted_tasks(conn)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT id, kind, uuid FROM snip_task WHERE state = :state ORDER BY id",
+    )?;
+    let tasks: Vec<(i64, String, Option<String>)> = stmt
+        .query_and_then(
+            &[(":state", &TaskState::Enqueued.as_str())],
+            |row| -> Result<(i64, String, Option<String>), rusqlite::Error> {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            },
+        )?
+        .flatten()
+        .collect();
+
+    for (id, kind, uuid) in tasks {
+        set_task_state(conn, id, TaskState::Processing, None)?;
+
+        let result: Result<(), Box<dyn Error>> = (|| {
+            match kind.as_str() {
+                "index" => {
+                    let id = Uuid::try_parse(uuid.as_deref().ok_or("index task missing uuid")?)?;
+                    let mut s = get_from_uuid(conn, &id)?;
+                    s.analyze()?;
+                    s.index(conn)
+                }
+                // the snip row is typically already gone by the time this task is
+                // drained, so this must not route through get_from_uuid
+                "remove" => {
+                    let id = Uuid::try_parse(uuid.as_deref().ok_or("remove task missing uuid")?)?;
+                    drop_word_indices_for_uuid(conn, &id)
+                }
+                "reindex" => index_all_items(conn),
+                other => Err(Box::new(SnipError::General(format!(
+                    "unrecognized task kind: {}",
+                    other
+                ))) as Box<dyn Error>),
+            }
+        })();
+
+        match result {
+            Ok(()) => set_task_state(conn, id, TaskState::Succeeded, None)?,
+            Err(e) => set_task_state(conn, id, TaskState::Failed, Some(&e.to_string()))?,
+        }
+    }
+    Ok(())
+}
+
+/// Adds a new document to the database. If `queue` is given, the new document is
+/// indexed off the write path via `IndexTask::Add` rather than left for the caller to
+/// index inline with `Snip::index`.
+pub fn insert_snip(
+    conn: &Connection,
+    s: &Snip,
+    queue: Option<&IndexQueue>,
+) -> Result<(), Box<dyn Error>> {
     let mut stmt =
         conn.prepare("INSERT INTO snip(uuid, timestamp, name, data) VALUES (?1, ?2, ?3, ?4)")?;
     stmt.execute([
@@ -421,6 +1080,9 @@ pub fn insert_snip(conn: &Connection, s: &Snip) -> Result<(), Box<dyn Error>> {
         s.text.clone(),
     ])?;
 
+    if let Some(queue) = queue {
+        queue.enqueue(IndexTask::Add(s.uuid))?;
+    }
     Ok(())
 }
 
@@ -454,6 +1116,67 @@ pub fn uuid_list(conn: &Connection, limit: usize) -> Result<Vec<Uuid>, Box<dyn E
     Ok(ids)
 }
 
+/// A single row of an export manifest: enough to locate a document's exported text
+/// file and re-verify it against tampering or truncation before a later import.
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    pub uuid: Uuid,
+    pub name: String,
+    pub timestamp: DateTime<FixedOffset>,
+    pub size: usize,
+    pub hash: String,
+}
+
+/// Exports each document in `uuids` into `dest_dir`: the document's text is written to
+/// `dest_dir/<uuid>.txt`, any attachments it has are written alongside via
+/// `snip::export_attachments_for_snip`, and a `manifest.tsv` is written recording each
+/// document's uuid, name, timestamp, byte size, and a SHA-256 content digest of its text.
+/// A later import can re-hash the `.txt` file and compare it against the manifest entry
+/// to reject a tampered or truncated export before inserting it.
+pub fn export_snips(
+    conn: &Connection,
+    uuids: &[Uuid],
+    dest_dir: &Path,
+) -> Result<Vec<ManifestEntry>, Box<dyn Error>> {
+    std::fs::create_dir_all(dest_dir)?;
+    let mut entries = Vec::new();
+
+    for uuid in uuids {
+        let s = get_from_uuid(conn, uuid)?;
+        std::fs::write(dest_dir.join(format!("{}.txt", s.uuid)), &s.text)?;
+        snip::export_attachments_for_snip(conn, s.uuid, dest_dir)?;
+
+        entries.push(ManifestEntry {
+            uuid: s.uuid,
+            name: s.name,
+            timestamp: s.timestamp,
+            size: s.text.len(),
+            hash: snip::hash_data(s.text.as_bytes()),
+        });
+    }
+
+    write_manifest(dest_dir, &entries)?;
+    Ok(entries)
+}
+
+/// Writes (overwriting) the `manifest.tsv` accompanying an export: one tab-separated
+/// line per document, `uuid\ttimestamp\tsize\thash\tname`.
+fn write_manifest(dest_dir: &Path, entries: &[ManifestEntry]) -> Result<(), Box<dyn Error>> {
+    let mut data = String::new();
+    for e in entries {
+        data.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            e.uuid,
+            e.timestamp.to_rfc3339(),
+            e.size,
+            e.hash,
+            e.name
+        ));
+    }
+    std::fs::write(dest_dir.join("manifest.tsv"), data)?;
+    Ok(())
+}
+
 /// Read all data from standard input, line by line, and return it as a String.
 pub fn read_lines_from_stdin() -> Result<String, Box<dyn Error>> {
     let mut data = String::new();
@@ -461,8 +1184,15 @@ pub fn read_lines_from_stdin() -> Result<String, Box<dyn Error>> {
     Ok(data)
 }
 
-/// Remove a document matching given uuid
-pub fn remove_snip(conn: &Connection, id: Uuid) -> Result<(), Box<dyn Error>> {
+/// Remove a document matching given uuid. If `queue` is given, removing the document's
+/// word indices is deferred off the write path via `IndexTask::Remove` instead of
+/// running inline here; the task runs against `uuid` alone (not a fetched `Snip`), so
+/// it is safe to drain after the `snip` row below has already been deleted.
+pub fn remove_snip(
+    conn: &Connection,
+    id: Uuid,
+    queue: Option<&IndexQueue>,
+) -> Result<(), Box<dyn Error>> {
     let mut s = get_from_uuid(conn, &id)?;
     // collect and remove attachments
     s.collect_attachments(conn)?;
@@ -471,7 +1201,10 @@ pub fn remove_snip(conn: &Connection, id: Uuid) -> Result<(), Box<dyn Error>> {
     }
 
     // remove terms from the index
-    s.drop_word_indices(conn)?;
+    match queue {
+        Some(queue) => queue.enqueue(IndexTask::Remove(id))?,
+        None => s.drop_word_indices(conn)?,
+    }
 
     // remove the document
     let mut stmt = conn.prepare("DELETE FROM snip WHERE uuid = ?1")?;
@@ -566,6 +1299,96 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_read_file_lossy_replaces_invalid_utf8_instead_of_erroring() -> Result<(), Box<dyn Error>> {
+        let root = std::env::temp_dir().join(format!("snip-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&root)?;
+        let path = root.join("invalid.txt");
+
+        // "valid " followed by a stray continuation byte with no lead byte, then more
+        // valid text; std::fs::read_to_string would hard-fail on this
+        let mut bytes = b"valid ".to_vec();
+        bytes.push(0x80);
+        bytes.extend_from_slice(b" text");
+        std::fs::write(&path, &bytes)?;
+
+        assert!(std::fs::read_to_string(&path).is_err());
+
+        let text = read_file_lossy(path.to_str().expect("utf8 path"))?;
+        assert_eq!(text, "valid \u{fffd} text");
+
+        std::fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_queue_add_and_flush() -> Result<(), Box<dyn Error>> {
+        // the worker thread opens its own connection, so this needs a real file on disk
+        // rather than the in-memory database `prepare_database` uses
+        let db_path =
+            std::env::temp_dir().join(format!("snip-test-index-queue-{}.db", Uuid::new_v4()));
+        let conn = Connection::open(&db_path)?;
+        snip::create_snip_tables(&conn)?;
+        import_snip_data(&conn)?;
+
+        let id = Uuid::try_parse(ID_STR)?;
+        clear_index(&conn)?; // verify the queue itself is what (re)builds the index
+
+        let queue = IndexQueue::spawn(db_path.clone());
+        queue.enqueue(IndexTask::Add(id))?;
+        queue.flush()?;
+
+        let mut stmt = conn.prepare("SELECT COUNT(*) FROM snip_index_rs WHERE uuid = :uuid")?;
+        let count: i64 = stmt.query_row(&[(":uuid", &id.to_string())], |row| row.get(0))?;
+        assert!(count > 0);
+
+        drop(stmt);
+        drop(conn);
+        let _ = std::fs::remove_file(&db_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_enqueue_index_and_drain_tasks() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        let id = Uuid::try_parse(ID_STR)?;
+        clear_index(&conn)?; // verify drain_tasks itself is what (re)builds the index
+
+        let task_id = enqueue_index(&conn, id)?;
+        let status = task_status(&conn, task_id)?.expect("task should exist");
+        assert_eq!(status.state, TaskState::Enqueued);
+        assert_eq!(status.uuid, Some(id));
+
+        drain_tasks(&conn)?;
+
+        let status = task_status(&conn, task_id)?.expect("task should still exist");
+        assert_eq!(status.state, TaskState::Succeeded);
+
+        let mut stmt = conn.prepare("SELECT COUNT(*) FROM snip_index_rs WHERE uuid = :uuid")?;
+        let count: i64 = stmt.query_row(&[(":uuid", &id.to_string())], |row| row.get(0))?;
+        assert!(count > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_interrupted_tasks_resumes_on_drain() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        let id = Uuid::try_parse(ID_STR)?;
+        clear_index(&conn)?;
+
+        let task_id = enqueue_index(&conn, id)?;
+        // simulate a worker that crashed mid-task, leaving the row stuck in "processing"
+        set_task_state(&conn, task_id, TaskState::Processing, None)?;
+
+        drain_tasks(&conn)?;
+
+        let status = task_status(&conn, task_id)?.expect("task should still exist");
+        assert_eq!(status.state, TaskState::Succeeded);
+
+        Ok(())
+    }
+
     #[test]
     fn test_get_from_uuid() -> Result<(), Box<dyn Error>> {
         let conn = prepare_database().expect("preparing in-memory database");
@@ -623,6 +1446,140 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_index_writes_separate_name_field_rows() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        let mut s = Snip {
+            uuid: Uuid::new_v4(),
+            name: "zzyzxwombat report".to_string(),
+            text: "this document discusses other things entirely".to_string(),
+            timestamp: chrono::Local::now().fixed_offset(),
+            analysis: SnipAnalysis { words: Vec::new() },
+            attachments: Vec::new(),
+        };
+        conn.prepare(
+            "INSERT INTO snip(uuid, timestamp, name, data) VALUES (:uuid, :timestamp, :name, :data)",
+        )?
+        .execute(rusqlite::named_params! {
+            ":uuid": s.uuid.to_string(),
+            ":timestamp": s.timestamp.to_rfc3339(),
+            ":name": s.name,
+            ":data": s.text,
+        })?;
+        s.index(&conn)?;
+
+        let field: String = conn.query_row(
+            "SELECT field FROM snip_index_rs WHERE uuid = :uuid AND term = 'zzyzxwombat'",
+            &[(":uuid", &s.uuid.to_string())],
+            |row| row.get(0),
+        )?;
+        assert_eq!(field, "name");
+
+        let field: String = conn.query_row(
+            "SELECT field FROM snip_index_rs WHERE uuid = :uuid AND term = 'document'",
+            &[(":uuid", &s.uuid.to_string())],
+            |row| row.get(0),
+        )?;
+        assert_eq!(field, "body");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_writes_prefix_index_rows() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        let id = Uuid::try_parse(ID_STR)?;
+        let mut s = get_from_uuid(&conn, &id)?;
+        s.index(&conn)?;
+
+        let count: i64 = conn.query_row(
+            "SELECT count FROM snip_prefix_index_rs WHERE uuid = :uuid AND prefix = 'lor'",
+            &[(":uuid", &id.to_string())],
+            |row| row.get(0),
+        )?;
+        assert!(count > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_all_items_prunes_rare_prefixes() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        index_all_items(&conn)?;
+
+        // a single-grapheme prefix like "l" should complete to enough distinct terms to
+        // survive the corpus-wide prune, since the fixture corpus is not trivially small
+        let survivors: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM snip_prefix_index_rs WHERE prefix = 'l'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert!(survivors > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_with_attachments() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        let id = Uuid::try_parse(ID_STR)?;
+
+        let root = std::env::temp_dir().join(format!("snip-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&root)?;
+        let path = root.join("notes.txt");
+        std::fs::write(&path, b"zzyzxwombat")?;
+        snip::add_attachment(&conn, id, &path)?;
+        std::fs::remove_dir_all(&root)?;
+
+        let mut s = get_from_uuid(&conn, &id)?;
+        s.index_with_attachments(&conn)?;
+
+        let matched: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM snip_index_rs WHERE uuid = :uuid AND term LIKE 'zzyzxwombat%'",
+            &[(":uuid", &id.to_string())],
+            |row| row.get(0),
+        )?;
+        assert_eq!(matched, 1);
+
+        // the snip's own stored text is untouched by indexing its attachments
+        let s_after = get_from_uuid(&conn, &id)?;
+        assert_eq!(s_after.text, s.text);
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_snips_writes_manifest_and_attachments() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        let id = Uuid::try_parse(ID_STR)?;
+
+        let root = std::env::temp_dir().join(format!("snip-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&root)?;
+        let attachment_path = root.join("notes.txt");
+        std::fs::write(&attachment_path, b"attached data")?;
+        snip::add_attachment(&conn, id, &attachment_path)?;
+
+        let s = get_from_uuid(&conn, &id)?;
+        let dest = root.join("export");
+        let entries = export_snips(&conn, &[id], &dest)?;
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].uuid, id);
+        assert_eq!(entries[0].size, s.text.len());
+        assert_eq!(entries[0].hash, snip::hash_data(s.text.as_bytes()));
+
+        let text = std::fs::read_to_string(dest.join(format!("{}.txt", id)))?;
+        assert_eq!(text, s.text);
+
+        let manifest = std::fs::read_to_string(dest.join("manifest.tsv"))?;
+        assert!(manifest.contains(&id.to_string()));
+        assert!(manifest.contains(&entries[0].hash));
+
+        assert!(dest.join(id.to_string()).join("notes.txt").exists());
+
+        std::fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
     #[test]
     fn test_insert_snip() -> Result<(), Box<dyn Error>> {
         let conn = prepare_database().expect("preparing in-memory database");
@@ -636,7 +1593,7 @@ mod tests {
             analysis: SnipAnalysis { words: Vec::new() },
             attachments: Vec::new(),
         };
-        insert_snip(&conn, &s)?;
+        insert_snip(&conn, &s, None)?;
 
         // verify
         let mut stmt = conn.prepare("SELECT uuid FROM snip WHERE uuid = ?")?;
@@ -658,7 +1615,7 @@ mod tests {
         let conn = prepare_database().expect("preparing in-memory database");
         let id = Uuid::try_parse(ID_STR)?;
         let attachment_id = Uuid::try_parse(ID_ATTACH_STR)?;
-        remove_snip(&conn, id)?;
+        remove_snip(&conn, id, None)?;
 
         // verify attachment was deleted
         if get_attachment_from_uuid(&conn, attachment_id).is_ok() {
@@ -690,7 +1647,7 @@ mod tests {
             analysis: SnipAnalysis { words: Vec::new() }, // dynamic data, not database
             attachments: Vec::new(),                      // dynamic data, not database
         };
-        expect.update(&conn)?;
+        expect.update(&conn, None)?;
 
         // verify
         let s = get_from_uuid(&conn, &id)?;
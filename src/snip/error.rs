@@ -1,10 +1,14 @@
 use std::error::Error;
 use std::fmt;
+use uuid::Uuid;
 
 /// Errors for Snip Analysis
 pub enum SnipError {
+    Ambiguous(Vec<Uuid>),
     Analysis(String),
+    DecryptionFailed(String),
     General(String),
+    KeyMismatch(String),
     UuidMultipleMatches(String),
     SearchNoMatches(String),
     UuidNotFound(String),
@@ -15,8 +19,15 @@ impl Error for SnipError {}
 impl fmt::Display for SnipError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            SnipError::Ambiguous(ids) => write!(
+                f,
+                "partial uuid matches {} snips, provide more characters to disambiguate",
+                ids.len()
+            ),
             SnipError::Analysis(s) => write!(f, "Analysis encountered an error: {}", s),
+            SnipError::DecryptionFailed(s) => write!(f, "decryption failed: {}", s),
             SnipError::General(s) => write!(f, "{}", s),
+            SnipError::KeyMismatch(s) => write!(f, "{}", s),
             SnipError::UuidMultipleMatches(s) => write!(f, "{}", s),
             SnipError::SearchNoMatches(s) => write!(f, "{}", s),
             SnipError::UuidNotFound(s) => write!(f, "uuid {} was not found", s),
@@ -27,6 +38,13 @@ impl fmt::Display for SnipError {
 impl fmt::Debug for SnipError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            SnipError::Ambiguous(ids) => write!(
+                f,
+                "{{ SnipError::Ambiguous({:?}) file: {}, line: {} }}",
+                ids,
+                file!(),
+                line!()
+            ),
             SnipError::Analysis(s) => write!(
                 f,
                 "{{ SnipError::Analysis({}) file: {}, line: {} }}",
@@ -34,6 +52,13 @@ impl fmt::Debug for SnipError {
                 file!(),
                 line!()
             ),
+            SnipError::DecryptionFailed(s) => write!(
+                f,
+                "{{ SnipError::DecryptionFailed({}) file: {}, line: {} }}",
+                s,
+                file!(),
+                line!()
+            ),
             SnipError::General(s) => write!(
                 f,
                 "{{ SnipError::General({}) file: {}, line: {} }}",
@@ -41,6 +66,13 @@ impl fmt::Debug for SnipError {
                 file!(),
                 line!()
             ),
+            SnipError::KeyMismatch(s) => write!(
+                f,
+                "{{ SnipError::KeyMismatch({}) file: {}, line: {} }}",
+                s,
+                file!(),
+                line!()
+            ),
             SnipError::UuidMultipleMatches(s) => write!(
                 f,
                 "{{ SnipError::UuidMultipleMatches({}) file: {}, line: {} }}",
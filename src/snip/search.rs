@@ -1,16 +1,331 @@
-use crate::snip::SnipError;
-use rusqlite::Connection;
-use std::collections::HashMap;
+use crate::snip::bitmap;
+use crate::snip::{is_stop_word, SnipError, WordIndex};
+use roaring::RoaringBitmap;
+use rusqlite::{Connection, OptionalExtension};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use uuid::Uuid;
 
-#[derive(Debug)]
+/// Memoizes term postings, phrase/boolean set-operation results, and per-document term
+/// positions for the lifetime of a single search, so a compound or ranked query reads
+/// each distinct term from SQLite at most once rather than re-querying it for every node
+/// or candidate document that references it.
+pub struct QueryContext<'conn> {
+    conn: &'conn Connection,
+    postings: RefCell<HashMap<String, HashSet<Uuid>>>,
+    set_ops: RefCell<HashMap<String, HashSet<Uuid>>>,
+    positions: RefCell<HashMap<(Uuid, String), Vec<usize>>>,
+    word_indices: RefCell<HashMap<String, Vec<(Uuid, WordIndex)>>>,
+}
+
+/// Caches term→document-uuid postings and (document, term)→position lookups across
+/// however many `search_structured_cached`/`search_prefix_cached` calls share this
+/// context, so a term or document/term pair looked up once — whether by
+/// `search_prefix_cached`, by `search_structured_cached`'s BUILD OUTPUT phase, or by a
+/// prior query entirely — is never re-read from `snip_index_rs` again for the context's
+/// lifetime. The INCLUDE/EXCLUDE bitmaps of `search_structured_cached` are not kept here;
+/// they live in a cache that lasts a single call. Modeled on Meilisearch's
+/// `DatabaseCache`; narrower in scope than `QueryContext` (no boolean set-op or phrase
+/// memoization), since it backs the flat `SearchQuery` pipeline rather than the
+/// `Operation`-tree one.
+pub struct SearchContext<'conn> {
+    conn: &'conn Connection,
+    term_uuids: RefCell<HashMap<String, Vec<Uuid>>>,
+    term_positions: RefCell<HashMap<(Uuid, String), Vec<usize>>>,
+}
+
+impl<'conn> SearchContext<'conn> {
+    pub fn new(conn: &'conn Connection) -> Self {
+        SearchContext {
+            conn,
+            term_uuids: RefCell::new(HashMap::new()),
+            term_positions: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Returns `term`'s document-uuid postings, querying `snip_index_rs` only the first
+    /// time `term` is requested on this context.
+    fn cached_uuids_matching_term(&self, term: &str) -> Result<Vec<Uuid>, Box<dyn Error>> {
+        if let Some(cached) = self.term_uuids.borrow().get(term) {
+            return Ok(cached.clone());
+        }
+        let uuids = search_uuids_matching_term(self.conn, &term.to_string())?;
+        self.term_uuids.borrow_mut().insert(term.to_string(), uuids.clone());
+        Ok(uuids)
+    }
+
+    /// Returns `term`'s positions within `uuid`'s document, querying `snip_index_rs` only
+    /// the first time this (document, term) pair is requested on this context.
+    fn cached_term_positions(&self, uuid: &Uuid, term: &str) -> Result<Vec<usize>, Box<dyn Error>> {
+        let key = (*uuid, term.to_string());
+        if let Some(cached) = self.term_positions.borrow().get(&key) {
+            return Ok(cached.clone());
+        }
+        let positions = get_term_positions(self.conn, uuid, &term.to_string())?;
+        self.term_positions.borrow_mut().insert(key, positions.clone());
+        Ok(positions)
+    }
+}
+
+impl<'conn> QueryContext<'conn> {
+    pub fn new(conn: &'conn Connection) -> Self {
+        QueryContext {
+            conn,
+            postings: RefCell::new(HashMap::new()),
+            set_ops: RefCell::new(HashMap::new()),
+            positions: RefCell::new(HashMap::new()),
+            word_indices: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the document set for an exact-term lookup, reading `snip_index_rs` only
+    /// the first time `term` is requested. Also unions in the postings of any registered
+    /// synonym of `term`, so a query for one word of a synonym group matches documents
+    /// that only contain the other.
+    fn term_postings(&self, term: &str) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+        self.cached_postings(&format!("term:{}", term), || {
+            let mut result: HashSet<Uuid> = search_uuids_matching_term(self.conn, &term.to_string())?
+                .into_iter()
+                .collect();
+            for synonym in synonyms_for(self.conn, term)? {
+                result.extend(search_uuids_matching_term(self.conn, &synonym)?);
+            }
+            Ok(result)
+        })
+    }
+
+    /// Returns the document set for a prefix lookup, cached separately from an exact
+    /// lookup of the same string so the two can't collide.
+    fn prefix_postings(&self, prefix: &str) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+        self.cached_postings(&format!("prefix:{}", prefix), || {
+            Ok(prefix_match(self.conn, prefix)?.into_iter().collect())
+        })
+    }
+
+    /// Returns the document set for a phrase lookup, cached by its exact term sequence.
+    fn phrase_postings(&self, terms: &[String]) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+        self.cached_postings(&format!("phrase:{}", terms.join(" ")), || {
+            evaluate_phrase(self.conn, terms)
+        })
+    }
+
+    /// Returns the document set for a `NEAR/n` proximity lookup, cached by its term pair
+    /// and distance.
+    fn near_postings(&self, a: &str, b: &str, max_gap: u64) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+        self.cached_postings(&format!("near:{}:{}:{}", a, b, max_gap), || {
+            evaluate_near(self.conn, a, b, max_gap)
+        })
+    }
+
+    fn cached_postings(
+        &self,
+        key: &str,
+        compute: impl FnOnce() -> Result<HashSet<Uuid>, Box<dyn Error>>,
+    ) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+        if let Some(cached) = self.postings.borrow().get(key) {
+            return Ok(cached.clone());
+        }
+        let result = compute()?;
+        self.postings.borrow_mut().insert(key.to_string(), result.clone());
+        Ok(result)
+    }
+
+    /// Returns the result of an `And`/`Or` set operation, keyed by its node kind and the
+    /// sorted set of leaf terms it combines, computing it with `compute` on first use.
+    fn cached_set_op(
+        &self,
+        key: &str,
+        compute: impl FnOnce() -> Result<HashSet<Uuid>, Box<dyn Error>>,
+    ) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+        if let Some(cached) = self.set_ops.borrow().get(key) {
+            return Ok(cached.clone());
+        }
+        let result = compute()?;
+        self.set_ops.borrow_mut().insert(key.to_string(), result.clone());
+        Ok(result)
+    }
+
+    /// Returns a document's stored positions for `term`, reading `snip_index_rs` only
+    /// the first time this (document, term) pair is requested.
+    pub fn term_positions(&self, uuid: &Uuid, term: &str) -> Result<Vec<usize>, Box<dyn Error>> {
+        let key = (*uuid, term.to_string());
+        if let Some(cached) = self.positions.borrow().get(&key) {
+            return Ok(cached.clone());
+        }
+        let result = get_term_positions(self.conn, uuid, &term.to_string())?;
+        self.positions.borrow_mut().insert(key, result.clone());
+        Ok(result)
+    }
+
+    /// Returns every document's merged `WordIndex` for `term` (its `body`/`name` field
+    /// rows combined into one count and one sorted position list per document), reading
+    /// `snip_index_rs` only the first time `term` is requested. Backs `get_word_index` so
+    /// a ranking stage that needs both a term's postings and its per-document positions
+    /// pays for one query instead of a postings lookup followed by a separate
+    /// position lookup per surviving document.
+    fn term_word_indices(&self, term: &str) -> Result<Vec<(Uuid, WordIndex)>, Box<dyn Error>> {
+        if let Some(cached) = self.word_indices.borrow().get(term) {
+            return Ok(cached.clone());
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT uuid, count, positions FROM snip_index_rs WHERE term = :term")?;
+        let rows = stmt.query_and_then(
+            &[(":term", &term)],
+            |row| -> Result<(String, u64, String), rusqlite::Error> {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            },
+        )?;
+
+        let mut by_uuid: HashMap<Uuid, WordIndex> = HashMap::new();
+        for row in rows.flatten() {
+            let (uuid_str, count, positions_str) = row;
+            let uuid = Uuid::try_parse(&uuid_str)?;
+            let positions = WordIndex::positions_to_u64(positions_str)?;
+            let entry = by_uuid.entry(uuid).or_insert_with(|| WordIndex {
+                count: 0,
+                positions: Vec::new(),
+                term: term.to_string(),
+            });
+            entry.count += count;
+            entry.positions.extend(positions);
+        }
+
+        let mut result: Vec<(Uuid, WordIndex)> = by_uuid.into_iter().collect();
+        for (_, index) in result.iter_mut() {
+            index.positions.sort_unstable();
+        }
+
+        self.word_indices
+            .borrow_mut()
+            .insert(term.to_string(), result.clone());
+        Ok(result)
+    }
+
+    /// Returns `term`'s merged `WordIndex` for one specific `uuid`, or `None` if that
+    /// document doesn't contain the term, resolving the term's full posting list through
+    /// `term_word_indices` so repeated lookups of the same term across different
+    /// documents share one query.
+    pub fn get_word_index(&self, term: &str, uuid: &Uuid) -> Result<Option<WordIndex>, Box<dyn Error>> {
+        Ok(self
+            .term_word_indices(term)?
+            .into_iter()
+            .find(|(candidate, _)| candidate == uuid)
+            .map(|(_, index)| index))
+    }
+
+    /// Every uuid in the corpus, i.e. the ceiling a bare or top-level `Operation::Not`
+    /// subtracts from. Mirrors `bitmap::universe_bitmap`; cached like any other posting
+    /// set since a query with more than one negation would otherwise re-read `snip` once
+    /// per negation.
+    fn universe(&self) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+        self.cached_postings("universe", || universe_uuids(self.conn))
+    }
+}
+
+/// Every uuid in the `snip` table. Backs `QueryContext::universe`.
+fn universe_uuids(conn: &Connection) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT uuid FROM snip")?;
+    let rows = stmt.query_and_then([], |row| row.get::<_, String>(0))?;
+    let mut uuids = HashSet::new();
+    for row in rows.flatten() {
+        uuids.insert(Uuid::try_parse(&row)?);
+    }
+    Ok(uuids)
+}
+
+/// A stable key identifying an operation's leaves (by kind and text), used to memoize
+/// `And`/`Or` set-operation results regardless of how the leaves are ordered.
+fn operation_leaf_key(op: &Operation) -> String {
+    match op {
+        Operation::Query(t) => format!("q:{}", t),
+        Operation::Prefix(t) => format!("x:{}", t),
+        Operation::Phrase(terms) => format!("p:{}", terms.join(" ")),
+        Operation::Near(a, b, n) => format!("nr:{}:{}:{}", a, b, n),
+        Operation::Not(inner) => format!("n:{}", operation_leaf_key(inner)),
+        Operation::And(ops) | Operation::Or(ops) => {
+            let mut keys: Vec<String> = ops.iter().map(operation_leaf_key).collect();
+            keys.sort();
+            keys.join(",")
+        }
+    }
+}
+
+/// Same evaluation as `evaluate_operation`, but reading term postings and set-operation
+/// results through `ctx` so repeated terms across the tree are resolved from SQLite only
+/// once per search.
+pub fn evaluate_operation_cached(
+    ctx: &QueryContext,
+    op: &Operation,
+) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+    match op {
+        Operation::Query(term) => ctx.term_postings(term),
+        Operation::Prefix(prefix) => ctx.prefix_postings(prefix),
+        Operation::Phrase(terms) => ctx.phrase_postings(terms),
+        Operation::Near(a, b, max_gap) => ctx.near_postings(a, b, *max_gap),
+        Operation::Not(inner) => {
+            // a bare Not has meaning relative to the whole corpus: everything except
+            // whatever the inner expression matches
+            let negated = evaluate_operation_cached(ctx, inner)?;
+            let mut result = ctx.universe()?;
+            result.retain(|id| !negated.contains(id));
+            Ok(result)
+        }
+        Operation::Or(ops) => {
+            let key = format!("or:{}", operation_leaf_key(op));
+            ctx.cached_set_op(&key, || {
+                let mut result: HashSet<Uuid> = HashSet::new();
+                for op in ops {
+                    result.extend(evaluate_operation_cached(ctx, op)?);
+                }
+                Ok(result)
+            })
+        }
+        Operation::And(ops) => {
+            let key = format!("and:{}", operation_leaf_key(op));
+            ctx.cached_set_op(&key, || {
+                let mut positive: Option<HashSet<Uuid>> = None;
+                let mut negative: HashSet<Uuid> = HashSet::new();
+
+                for op in ops {
+                    if let Operation::Not(inner) = op {
+                        negative.extend(evaluate_operation_cached(ctx, inner)?);
+                        continue;
+                    }
+                    let set = evaluate_operation_cached(ctx, op)?;
+                    positive = Some(match positive {
+                        Some(mut acc) => {
+                            acc.retain(|id| set.contains(id));
+                            acc
+                        }
+                        None => set,
+                    });
+                }
+
+                let mut result = match positive {
+                    Some(acc) => acc,
+                    None => ctx.universe()?,
+                };
+                result.retain(|id| !negative.contains(id));
+                Ok(result)
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct SearchQuery {
     pub terms_include: Vec<String>, // all terms must be present in a document
     pub terms_exclude: Vec<String>, // none of these terms may be present in a document
     pub terms_optional: Vec<String>, // neither mandatory nor disqualifying, but increase score if present
     pub method: SearchMethod,        // search the index, document text field, etc.
     pub uuids: Vec<Uuid>,
+    /// If set, the last entry of `terms_include` is treated as a still-being-typed
+    /// prefix rather than an exact stem, matched (via `fuzzy_prefix_matches`) against
+    /// every indexed term whose prefix comes within this many edits of it.
+    pub prefix_distance: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -25,76 +340,138 @@ pub struct SearchQueryItem {
     pub matches: HashMap<String, Vec<usize>>, // term, positions
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SearchMethod {
     IndexStem, // index of stemmed terms parsed from document text
     IndexWord, // index of unmodified words parsed from document text
     Literal,   // direct matching on unmodified document text
+    /// Like `IndexStem`, but each include/exclude term also matches any indexed term
+    /// within the given Levenshtein edit distance, via `fuzzy_term_matches`'s automaton.
+    IndexFuzzy(usize),
+    /// Like `IndexFuzzy`, but the edit distance isn't fixed by the caller — each term
+    /// gets its own budget from `max_typo_distance(term.chars().count())`, so a short
+    /// term stays exact while a long one tolerates more drift.
+    IndexFuzzyAuto,
+    /// Like `IndexStem`, but the trailing `terms_include` entry is treated as a
+    /// still-being-typed prefix (exact, zero-edit autocomplete) without the caller
+    /// needing to also set `SearchQuery::prefix_distance` — equivalent to pairing
+    /// `IndexStem` with `prefix_distance: Some(0)`.
+    IndexPrefix,
 }
 
-impl SearchQueryResult {
-    /*
-    /// Score the search results using both the result and the query. This will allow for
-    /// scores to be based on the relationship between individual results.
-    pub fn score_search_query(query: SearchQuery, result: &mut SearchQueryResult) {
+/// Scores `result.items` from the term positions already gathered in `matches`, combining
+/// two signals per document: a TF-IDF-ish term weight (`tf = positions.len()` times
+/// `idf = ln(N / df)`, `N` the total indexed document count from `corpus_stats`, `df` the
+/// number of documents containing the term from `document_frequency`, summed across every
+/// matched term — include and optional terms are weighted the same way, an optional term
+/// just never kept a document out of `matches` in the first place), and a proximity bonus
+/// (`terms_present / (1 + span)`, `span` the smallest window containing at least one
+/// occurrence of every matched term, via `min_term_span`). Stores the sum in
+/// `SearchQueryItem::score` and sorts `result.items` descending by it.
+pub fn score_search_query(conn: &Connection, result: &mut SearchQueryResult) -> Result<(), Box<dyn Error>> {
+    let (doc_count, _) = corpus_stats(conn)?;
+
+    for item in result.items.iter_mut() {
+        let mut weight = 0.0;
+        for (term, positions) in &item.matches {
+            let df = document_frequency(conn, term)?;
+            if df == 0 {
+                continue;
+            }
+            let idf = (doc_count as f64 / df as f64).ln();
+            weight += positions.len() as f64 * idf;
+        }
+
+        let positions_per_term: Vec<Vec<usize>> = item.matches.values().cloned().collect();
+        let proximity = match min_term_span(&positions_per_term) {
+            Some(span) => item.matches.len() as f64 / (1.0 + span as f64),
+            None => 0.0,
+        };
+
+        item.score = Some(weight + proximity);
     }
-     */
+
+    result.items.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    Ok(())
 }
 
 /// Search using a logical combination of terms that must all be present, terms that disqualify
 /// if present, and terms that are optional but add to the result score
+///
+/// The include/exclude/uuid candidate phase is resolved entirely as compressed
+/// `roaring::RoaringBitmap` set ops (`bitmap::term_bitmap`/`term_bitmap_fuzzy`/
+/// `term_bitmap_prefix`, intersected with `&`, unioned with `|`, subtracted with `-`)
+/// rather than comparing `Vec<Uuid>`s term-by-term, via `cached_term_bitmap`'s
+/// per-invocation cache so a term repeated across `terms_include`/`terms_exclude` is only
+/// resolved once.
+/// Per-document positions (needed only for excerpts) are still fetched lazily, and only
+/// for documents that survive the bitmap phase.
+///
+/// A thin wrapper over `search_structured_cached` that builds a throwaway
+/// `SearchContext`, for callers running a single query. A caller running several queries
+/// against the same connection should build one `SearchContext` and call
+/// `search_structured_cached` directly, so position lookups a later query repeats are
+/// served from cache rather than re-reading `snip_index_rs`.
 pub fn search_structured(
     conn: &Connection,
     search_query: SearchQuery,
 ) -> Result<SearchQueryResult, Box<dyn Error>> {
+    let ctx = SearchContext::new(conn);
+    search_structured_cached(&ctx, search_query)
+}
+
+/// Same as `search_structured`, but runs against a caller-supplied `SearchContext` rather
+/// than a throwaway one, so its term/position lookups are shared with whatever else has
+/// run (or will run) on that context.
+pub fn search_structured_cached(
+    ctx: &SearchContext,
+    search_query: SearchQuery,
+) -> Result<SearchQueryResult, Box<dyn Error>> {
+    let conn = ctx.conn;
     let mut query_result = SearchQueryResult { items: Vec::new() };
-    let mut include_results: Vec<Uuid> = Vec::new();
-    let mut exclude_results: Vec<Uuid> = Vec::new();
+    let mut bitmap_cache: HashMap<String, RoaringBitmap> = HashMap::new();
+
+    let last_include_idx = search_query.terms_include.len().saturating_sub(1);
 
     // if search uuids are not set, search all documents
-    if search_query.uuids.is_empty() {
-        // INCLUDE
+    let include_bitmap = if search_query.uuids.is_empty() {
+        // INCLUDE: AND every term's posting bitmap together
+        let mut include_bitmap: Option<RoaringBitmap> = None;
         for (i, term) in search_query.terms_include.iter().enumerate() {
-            let mut result = search_uuids_matching_term(conn, term)?;
-            // println!("iter result: {:?}", result);
-            // push all results on first run for next iteration comparison
-            if i == 0 {
-                include_results.append(&mut result);
-                // break if there was only one term
-                if search_query.terms_include.len() == 1 {
-                    break;
+            let bitmap = if i == last_include_idx {
+                if let Some(max_distance) = effective_prefix_distance(&search_query) {
+                    cached_prefix_bitmap(conn, &mut bitmap_cache, term, max_distance)?
+                } else {
+                    cached_term_bitmap(conn, &mut bitmap_cache, term, &search_query.method)?
                 }
-                continue;
-            }
-
-            // filter non-matching uuids
-            include_results.retain_mut(|id| result.contains(id));
+            } else {
+                cached_term_bitmap(conn, &mut bitmap_cache, term, &search_query.method)?
+            };
+            include_bitmap = Some(match include_bitmap {
+                Some(acc) => acc & bitmap,
+                None => bitmap,
+            });
         }
-        // println!("include_results: {:?}", include_results);
 
-        // EXCLUDE
-        for term in search_query.terms_exclude {
-            let result = search_uuids_matching_term(conn, &term)?;
-            for r in result {
-                if !exclude_results.contains(&r) {
-                    exclude_results.push(r);
-                }
-            }
+        // EXCLUDE: OR every exclude term's bitmap together, then ANDNOT it from INCLUDE
+        let mut exclude_bitmap = RoaringBitmap::new();
+        for term in &search_query.terms_exclude {
+            exclude_bitmap |= cached_term_bitmap(conn, &mut bitmap_cache, term, &search_query.method)?;
         }
-        // println!("exclude_results: {:?}", exclude_results);
 
-        // SUBTRACT EXCLUDE FROM INCLUDE
-        include_results.retain_mut(|id| !exclude_results.contains(id));
-        // println!("filtered_results: {:?}", include_results);
+        include_bitmap.unwrap_or_default() - exclude_bitmap
     } else {
         // restrict search to supplied uuids
-        for uuid in search_query.uuids {
-            include_results.push(uuid);
-        }
-    }
+        bitmap::uuids_bitmap(conn, &search_query.uuids)?
+    };
 
-    // BUILD OUTPUT
-    for uuid in include_results {
+    // BUILD OUTPUT — positions are fetched lazily, only for the surviving document set
+    for ordinal in include_bitmap.iter() {
+        let uuid = bitmap::uuid_for_ordinal(conn, ordinal)?;
         let mut item = SearchQueryItem {
             uuid,
             score: None,
@@ -102,394 +479,3847 @@ pub fn search_structured(
         };
 
         // gather and push positions for each term
-        for term in search_query.terms_include.iter() {
-            let positions = get_term_positions(conn, &uuid, term)?;
+        for (i, term) in search_query.terms_include.iter().enumerate() {
+            let positions = if i == last_include_idx {
+                if let Some(max_distance) = effective_prefix_distance(&search_query) {
+                    prefix_term_positions_cached(ctx, &uuid, term, max_distance)?
+                } else {
+                    term_positions_fuzzy_cached(ctx, &uuid, term, &search_query.method)?
+                }
+            } else {
+                term_positions_fuzzy_cached(ctx, &uuid, term, &search_query.method)?
+            };
             item.matches.insert(term.clone(), positions);
         }
+
+        // gather positions for optional "should" terms too: these never narrowed the
+        // bitmap candidate set above, but a document that does contain one gets its
+        // positions folded into `matches` the same as a mandatory term, so it both
+        // boosts rank (sort_by_relevance's frequency tally sums every matched term's
+        // positions) and surfaces in excerpt/summary output
+        for term in &search_query.terms_optional {
+            let positions = term_positions_fuzzy_cached(ctx, &uuid, term, &search_query.method)?;
+            if !positions.is_empty() {
+                item.matches.insert(term.clone(), positions);
+            }
+        }
+
         query_result.items.push(item);
     }
 
+    score_search_query(conn, &mut query_result)?;
     Ok(query_result)
 }
 
-#[derive(Debug)]
-pub struct SearchResult {
-    pub items: HashMap<Uuid, Vec<SearchTermPositions>>,
+/// Resolves `term`'s posting bitmap the way `search_structured` resolves a non-trailing
+/// (or non-prefix) `terms_include`/`terms_exclude` entry — `bitmap::term_bitmap_fuzzy`
+/// under `SearchMethod::IndexFuzzy` and `IndexFuzzyAuto`, `bitmap::term_bitmap` otherwise —
+/// caching the result
+/// in `cache` so a term seen twice within the same `search_structured` call (e.g. repeated
+/// across `terms_include` and `terms_exclude`) is only fetched from `snip_term_bitmap`
+/// once.
+fn cached_term_bitmap(
+    conn: &Connection,
+    cache: &mut HashMap<String, RoaringBitmap>,
+    term: &str,
+    method: &SearchMethod,
+) -> Result<RoaringBitmap, Box<dyn Error>> {
+    if let Some(bitmap) = cache.get(term) {
+        return Ok(bitmap.clone());
+    }
+    let bitmap = match method {
+        SearchMethod::IndexFuzzy(max_distance) => bitmap::term_bitmap_fuzzy(conn, term, *max_distance)?,
+        SearchMethod::IndexFuzzyAuto => {
+            bitmap::term_bitmap_fuzzy(conn, term, max_typo_distance(term.chars().count()))?
+        }
+        _ => bitmap::term_bitmap(conn, term)?,
+    };
+    cache.insert(term.to_string(), bitmap.clone());
+    Ok(bitmap)
 }
 
-#[derive(Debug)]
-pub struct SearchTermPositions {
-    pub matches: HashMap<String, Vec<usize>>, // <term, Vec<positions>
+/// Like `cached_term_bitmap`, but for a trailing `terms_include` entry under
+/// `SearchQuery::prefix_distance`, resolved via `bitmap::term_bitmap_prefix`. Cached under
+/// a distinct key from `cached_term_bitmap` (prefix resolution matches a superset of what
+/// an exact/fuzzy lookup on the same text would), so the two never collide.
+fn cached_prefix_bitmap(
+    conn: &Connection,
+    cache: &mut HashMap<String, RoaringBitmap>,
+    term: &str,
+    max_distance: usize,
+) -> Result<RoaringBitmap, Box<dyn Error>> {
+    let key = format!("prefix:{}:{}", max_distance, term);
+    if let Some(bitmap) = cache.get(&key) {
+        return Ok(bitmap.clone());
+    }
+    let bitmap = bitmap::term_bitmap_prefix(conn, term, max_distance)?;
+    cache.insert(key, bitmap.clone());
+    Ok(bitmap)
 }
 
-#[derive(Clone, Debug)]
-pub struct SearchResultTerm {
-    pub uuid: Uuid,
-    pub term: String,
-    pub positions: Vec<usize>,
+/// The prefix edit distance `search_structured` should use for the trailing
+/// `terms_include` entry: `SearchQuery::prefix_distance` if explicitly set, otherwise 0
+/// when `SearchMethod::IndexPrefix` was chosen instead, otherwise `None` (not a prefix
+/// query at all).
+fn effective_prefix_distance(search_query: &SearchQuery) -> Option<usize> {
+    search_query.prefix_distance.or(match search_query.method {
+        SearchMethod::IndexPrefix => Some(0),
+        _ => None,
+    })
 }
 
-/// Returns ids of documents that match the given term
-pub fn search_data(conn: &Connection, term: &String) -> Result<Vec<Uuid>, Box<dyn Error>> {
-    let mut stmt = conn.prepare("SELECT uuid FROM snip WHERE data LIKE :term")?;
-    let term_fuzzy = format!("{} {}{}", "%", term, "%");
+/// Orders `result.items` in place by a ranking-rules cascade like the one
+/// `rank_candidates` uses, with total occurrences in place of its exactness and BM25
+/// rules: fewest typo corrections first, then the
+/// tightest window containing at least one occurrence of every include term (via
+/// `min_term_span` over each item's already-gathered `matches` positions), then most
+/// total occurrences across every matched term. A document where every term sits adjacent
+/// always outranks one where they're scattered, since span is compared before frequency;
+/// a single-term query has no span to measure (`min_term_span` always returns `Some(0)`
+/// for one term), so every candidate ties on proximity and the sort falls straight
+/// through to frequency.
+pub fn sort_by_relevance(
+    conn: &Connection,
+    result: &mut SearchQueryResult,
+    search_query: &SearchQuery,
+) -> Result<(), Box<dyn Error>> {
+    let mut keyed: Vec<(usize, Option<usize>, usize, SearchQueryItem)> =
+        Vec::with_capacity(result.items.len());
+    for item in result.items.drain(..) {
+        let typo_distance = search_query_typo_distance(conn, &item.uuid, search_query)?;
+        let positions_per_term: Vec<Vec<usize>> = item.matches.values().cloned().collect();
+        let proximity_span = min_term_span(&positions_per_term);
+        let frequency: usize = item.matches.values().map(|p| p.len()).sum();
+        keyed.push((typo_distance, proximity_span, frequency, item));
+    }
 
-    let query_iter = stmt.query_map(&[(":term", &term_fuzzy)], |row| {
-        let id_str: String = row.get(0)?;
-        Ok(id_str)
-    })?;
+    keyed.sort_by(|a, b| {
+        a.0.cmp(&b.0)
+            .then_with(|| span_rank(a.1).cmp(&span_rank(b.1)))
+            .then_with(|| b.2.cmp(&a.2))
+    });
+    result.items = keyed.into_iter().map(|(_, _, _, item)| item).collect();
+    Ok(())
+}
 
-    let mut results: Vec<Uuid> = Vec::new();
-    for i in query_iter {
-        let id_str = match i {
-            Ok(v) => v,
-            Err(e) => return Err(Box::new(e)),
+/// Sums, across every `terms_include` entry, the edit distance `search_structured` needed
+/// to match `uuid`: 0 for a term that matched exactly (or under a non-fuzzy
+/// `SearchMethod`), or else the distance of the closest derivation — among those actually
+/// occurring in this document — found via `fuzzy_term_matches`/`fuzzy_prefix_matches`.
+fn search_query_typo_distance(
+    conn: &Connection,
+    uuid: &Uuid,
+    search_query: &SearchQuery,
+) -> Result<usize, Box<dyn Error>> {
+    let last_include_idx = search_query.terms_include.len().saturating_sub(1);
+    let mut total = 0;
+    for (i, term) in search_query.terms_include.iter().enumerate() {
+        total += if i == last_include_idx {
+            if let Some(max_distance) = effective_prefix_distance(search_query) {
+                term_typo_distance_prefix(conn, uuid, term, max_distance)?
+            } else {
+                term_typo_distance(conn, uuid, term, &search_query.method)?
+            }
+        } else {
+            term_typo_distance(conn, uuid, term, &search_query.method)?
         };
-        match Uuid::parse_str(&id_str) {
-            Ok(v) => results.push(v),
-            Err(e) => return Err(Box::new(e)),
+    }
+    Ok(total)
+}
+
+/// The smallest edit distance, among `fuzzy_term_matches`' derivations of `term` that
+/// actually occur in `uuid`, needed to explain its match; 0 under any non-fuzzy method.
+fn term_typo_distance(
+    conn: &Connection,
+    uuid: &Uuid,
+    term: &str,
+    method: &SearchMethod,
+) -> Result<usize, Box<dyn Error>> {
+    let max_distance = match method {
+        SearchMethod::IndexFuzzy(max_distance) => *max_distance,
+        SearchMethod::IndexFuzzyAuto => max_typo_distance(term.chars().count()),
+        _ => return Ok(0),
+    };
+
+    let mut best: Option<usize> = None;
+    for derivation in fuzzy_term_matches(conn, term, max_distance)? {
+        if !get_term_positions(conn, uuid, &derivation.term)?.is_empty() {
+            let distance = edit_distance(term, &derivation.term, max_distance).unwrap_or(max_distance);
+            best = Some(best.map_or(distance, |b: usize| b.min(distance)));
         }
     }
-    // println!("results: {:?}", results);
-    Ok(results)
+    Ok(best.unwrap_or(0))
 }
 
-fn get_term_positions(
+/// Like `term_typo_distance`, but for the trailing `terms_include` entry under
+/// `SearchQuery::prefix_distance`, matched via `fuzzy_prefix_matches` instead and measured
+/// against each derivation's leading characters, as many as `term` has.
+fn term_typo_distance_prefix(
     conn: &Connection,
-    id: &Uuid,
+    uuid: &Uuid,
+    term: &str,
+    max_distance: usize,
+) -> Result<usize, Box<dyn Error>> {
+    let mut best: Option<usize> = None;
+    for derivation in fuzzy_prefix_matches(conn, term, max_distance)? {
+        if !get_term_positions(conn, uuid, &derivation.term)?.is_empty() {
+            let compared = truncate_chars(&derivation.term, term.chars().count());
+            let distance = edit_distance(term, compared, max_distance).unwrap_or(max_distance);
+            best = Some(best.map_or(distance, |b: usize| b.min(distance)));
+        }
+    }
+    Ok(best.unwrap_or(0))
+}
+
+/// Gathers `term`'s match positions for `uuid` for a trailing `terms_include` entry
+/// under `SearchQuery::prefix_distance`, merging positions from every indexed term whose
+/// prefix matched (sorted, deduplicated), mirroring `bitmap::term_bitmap_prefix`'s
+/// resolution of the same entry during the candidate phase. Routed through `ctx` so a
+/// (document, term) pair already looked up elsewhere on this context is served from
+/// cache rather than re-querying `snip_index_rs`.
+fn prefix_term_positions_cached(
+    ctx: &SearchContext,
+    uuid: &Uuid,
+    term: &str,
+    max_distance: usize,
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    let mut positions: Vec<usize> = Vec::new();
+    for derivation in fuzzy_prefix_matches(ctx.conn, term, max_distance)? {
+        positions.extend(ctx.cached_term_positions(uuid, &derivation.term)?);
+    }
+    positions.sort_unstable();
+    positions.dedup();
+    Ok(positions)
+}
+
+/// Gathers `term`'s match positions for `uuid` the way `search_structured_cached` does:
+/// under `SearchMethod::IndexFuzzy`/`IndexFuzzyAuto`, positions from every typo'd
+/// derivation of `term` found in this document are merged in (sorted, deduplicated), so
+/// `get_excerpt` surfaces excerpts from a misspelled occurrence just as it would an exact
+/// one. Routed through `ctx`'s position cache rather than `get_term_positions` directly.
+fn term_positions_fuzzy_cached(
+    ctx: &SearchContext,
+    uuid: &Uuid,
     term: &String,
+    method: &SearchMethod,
 ) -> Result<Vec<usize>, Box<dyn Error>> {
-    let mut stmt =
-        conn.prepare("SELECT positions FROM snip_index_rs WHERE uuid = :uuid AND term = :term")?;
-    let query_iter = stmt.query_map(&[(":uuid", &id.to_string()), (":term", term)], |row| {
-        let positions = row.get::<_, String>(0)?;
-        Ok(positions)
-    })?;
+    let max_distance = match method {
+        SearchMethod::IndexFuzzy(max_distance) => *max_distance,
+        SearchMethod::IndexFuzzyAuto => max_typo_distance(term.chars().count()),
+        _ => return ctx.cached_term_positions(uuid, term),
+    };
 
     let mut positions: Vec<usize> = Vec::new();
-    if let Some(positions_str) = query_iter.flatten().next() {
-        positions = positions_str
-            .split(',')
-            .map(|x| x.parse::<usize>().expect("converting db pos to usize"))
-            .collect();
+    for derivation in fuzzy_term_matches(ctx.conn, term, max_distance)? {
+        positions.extend(ctx.cached_term_positions(uuid, &derivation.term)?);
     }
+    positions.sort_unstable();
+    positions.dedup();
     Ok(positions)
 }
 
-/// Search the index and return uuids that contain term
-pub fn search_uuids_matching_term(
+/// A parsed boolean query tree, following Meilisearch's `Operation` shape: leaves are
+/// single terms or quoted phrases, combined by implicit/explicit AND, explicit OR, and
+/// leading `-` negation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    And(Vec<Operation>),
+    Or(Vec<Operation>),
+    Not(Box<Operation>),
+    Query(String),
+    Phrase(Vec<String>),
+    /// A bare word still being typed, resolved as a union of indexed terms sharing this
+    /// prefix rather than an exact stem match.
+    Prefix(String),
+    /// A `a NEAR/n b` proximity operator: matches documents where the two (stemmed)
+    /// terms occur within `n` word positions of each other, in either order.
+    Near(String, String, u64),
+}
+
+/// Stems a raw query word the same way `Snip::stem_words` stems document words, so a
+/// query leaf matches the stems actually written to `snip_index_rs` rather than the
+/// user's literal surface form. Routes through the same active `Analyzer` `Snip::index`
+/// uses, so switching languages with `set_stemmer_language` keeps queries and the index
+/// in sync.
+fn stem_term(raw: &str) -> String {
+    crate::snip::stem_word(raw)
+}
+
+/// Registers a one-directional synonym mapping: a query for `term` also matches
+/// documents containing `synonym`, expanded at evaluation time by `QueryContext::
+/// term_postings` rather than at index time, so the index itself never has to be
+/// rebuilt when the synonym list changes. Both sides are stemmed before storage, the
+/// same way a parsed query leaf is, so they compare against what `index()` actually
+/// wrote to `snip_index_rs`.
+pub fn add_synonym(conn: &Connection, term: &str, synonym: &str) -> Result<(), Box<dyn Error>> {
+    let term = stem_term(term);
+    let synonym = stem_term(synonym);
+    conn.execute(
+        "INSERT OR IGNORE INTO snip_synonym(term, synonym) VALUES (:term, :synonym)",
+        rusqlite::named_params! { ":term": term, ":synonym": synonym },
+    )?;
+    Ok(())
+}
+
+/// Registers a synonym mapping in both directions, so a query for either word matches
+/// documents containing the other.
+pub fn add_synonym_bidirectional(
     conn: &Connection,
-    term: &String,
-) -> Result<Vec<Uuid>, Box<dyn Error>> {
-    let mut ids: Vec<Uuid> = Vec::new();
-    let mut stmt = conn.prepare("SELECT uuid FROM snip_index_rs WHERE term = :term")?;
+    a: &str,
+    b: &str,
+) -> Result<(), Box<dyn Error>> {
+    add_synonym(conn, a, b)?;
+    add_synonym(conn, b, a)?;
+    Ok(())
+}
+
+/// Removes a one-directional synonym mapping, returning the number of rows deleted (0 or
+/// 1). To undo `add_synonym_bidirectional`, call this once for each direction.
+pub fn remove_synonym(conn: &Connection, term: &str, synonym: &str) -> Result<usize, Box<dyn Error>> {
+    let term = stem_term(term);
+    let synonym = stem_term(synonym);
+    let count = conn.execute(
+        "DELETE FROM snip_synonym WHERE term = :term AND synonym = :synonym",
+        rusqlite::named_params! { ":term": term, ":synonym": synonym },
+    )?;
+    Ok(count)
+}
+
+/// Returns the registered synonyms of `term` (expected to already be stemmed), consulted
+/// by `QueryContext::term_postings` so a `Query` leaf also resolves any synonym's
+/// postings.
+fn synonyms_for(conn: &Connection, term: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT synonym FROM snip_synonym WHERE term = :term")?;
     let rows = stmt.query_and_then(
         &[(":term", &term)],
-        |row| -> Result<String, Box<dyn Error>> {
-            let id: String = row.get(0)?;
-            Ok(id)
-        },
+        |row| -> Result<String, rusqlite::Error> { row.get(0) },
     )?;
 
-    for row in rows.flatten() {
-        let id = Uuid::try_parse(row.as_str())?;
-        ids.push(id);
+    let mut synonyms = Vec::new();
+    for row in rows {
+        synonyms.push(row?);
     }
-    Ok(ids)
+    Ok(synonyms)
 }
 
-pub fn search_all_present(
-    conn: &Connection,
-    terms: Vec<String>,
-) -> Result<SearchResult, Box<dyn Error>> {
-    let mut result = SearchResult {
-        items: HashMap::new(),
-    };
+/// Parses a `NEAR/<n>` proximity operator token (e.g. `"NEAR/5"`), returning its distance.
+fn near_distance(raw: &str) -> Option<u64> {
+    raw.strip_prefix("NEAR/")?.parse().ok()
+}
 
-    let mut result_prelim: Vec<SearchResultTerm> = Vec::new();
+/// A single tokenized query leaf, before it has been turned into an `Operation`: a bare
+/// word, a quoted phrase's raw (unstemmed) text, or a parenthesized group's raw inner
+/// text (re-tokenized and parsed recursively by `parse_query`).
+#[derive(Debug, Clone, PartialEq)]
+enum QueryToken {
+    Word(String),
+    Phrase(String),
+    Group(String),
+}
 
-    for term in terms {
-        let mut stmt =
-            conn.prepare("SELECT uuid, positions FROM snip_index_rs WHERE term = :term")?;
-        let query_iter = stmt.query_map(&[(":term", &term)], |row| {
-            let id = row.get::<_, String>(0)?;
-            let pos_str = row.get::<_, String>(1)?;
-            Ok((id, pos_str))
-        })?;
-        for id_str in query_iter.flatten() {
-            let uuid = Uuid::try_parse(id_str.0.as_str())?;
-            let positions: Vec<usize> = id_str
-                .1
-                .split(',')
-                .map(|x| {
-                    x.parse::<usize>()
-                        .expect("parsing positions from db string")
-                })
-                .collect();
-            result_prelim.push(SearchResultTerm {
-                uuid,
-                term: term.clone(),
-                positions,
-            });
-        }
-    }
+/// Parses a query string into an `Operation` tree. Words are combined with an implicit
+/// AND; the literal token `OR` starts a new alternative; `"quoted phrases"` become a
+/// single `Phrase` leaf; `(parenthesized groups)` are parsed recursively and treated as a
+/// single leaf, so `rust AND (tokio OR async)` groups correctly instead of `OR` binding
+/// across the whole query; `a NEAR/n b` fuses the two bare words around it into a single
+/// `Near` leaf; a leading `-` (on a word, a phrase, or a group) negates that leaf, and the
+/// literal token `NOT` negates whatever follows it the same way, so `rust NOT windows` and
+/// `rust -windows` parse identically; and the last bare word of the query (the one the
+/// user is presumably still typing) is resolved as a `Prefix` leaf instead of an exact
+/// `Query`, mirroring standard search-as-you-type behavior. Earlier words stay exact.
+/// Every leaf word is stemmed to match how `index()` stores terms; the `Prefix` leaf is
+/// the exception, since stemming a word still being typed would usually just mangle it.
+/// Stop-word `Query` leaves are dropped from any group of two or more leaves, the same
+/// terms `index()` never wrote to `snip_index_rs` in the first place; a group made up
+/// entirely of stop words is left intact, so a query that really is just "the" still runs
+/// rather than matching nothing.
+pub fn parse_query(input: &str) -> Operation {
+    let tokens = tokenize_query(input);
 
-    // add all matches to result hashmap
-    for rt in result_prelim {
-        let mut item = SearchTermPositions {
-            matches: HashMap::new(),
-        };
-        item.matches.insert(rt.term, rt.positions);
+    let last_word_idx = tokens
+        .iter()
+        .enumerate()
+        .rev()
+        .find(|(i, (negated, tok))| {
+            if *negated {
+                return false;
+            }
+            if !matches!(tok, QueryToken::Word(w) if w != "OR" && w != "NOT" && near_distance(w).is_none())
+            {
+                return false;
+            }
+            // a word immediately after a bare "NOT" is negated by it, not an eligible
+            // still-being-typed leaf
+            !matches!(
+                tokens.get(i.wrapping_sub(1)),
+                Some((false, QueryToken::Word(prev))) if prev == "NOT"
+            )
+        })
+        .map(|(i, _)| i);
 
-        // add to final results
-        if result.items.get(&rt.uuid).is_none() {
-            result.items.insert(rt.uuid, Vec::new());
+    let mut groups: Vec<Vec<Operation>> = vec![Vec::new()];
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let (negated, tok) = &tokens[i];
+
+        if !negated && matches!(tok, QueryToken::Word(w) if w == "OR") {
+            groups.push(Vec::new());
+            i += 1;
+            continue;
         }
-        result.items.get_mut(&rt.uuid).unwrap().push(item); // FIXME - no unwrap
-    }
-    Ok(result)
-}
 
-/// Search for a uuid matching the supplied partial string.
-/// The partial uuid must match a unique record to return the result.
-pub fn search_uuid(conn: &Connection, id_partial: &str) -> Result<Uuid, SnipError> {
-    let mut stmt = match conn.prepare("SELECT uuid from snip WHERE uuid LIKE :id LIMIT 2") {
-        Ok(v) => v,
-        Err(e) => {
-            println!("There was a problem preparing the search query: {}", e);
-            return Err(SnipError::General(format!("{}", e)));
+        // a bare "NOT" negates the following token, mirroring a leading '-' on it
+        if !negated && matches!(tok, QueryToken::Word(w) if w == "NOT") {
+            if let Some((false, next_tok)) = tokens.get(i + 1) {
+                let leaf = match next_tok {
+                    QueryToken::Phrase(raw) => {
+                        Operation::Phrase(raw.split_whitespace().map(stem_term).collect())
+                    }
+                    QueryToken::Group(raw) => parse_query(raw),
+                    QueryToken::Word(raw) => Operation::Query(stem_term(raw)),
+                };
+                groups
+                    .last_mut()
+                    .expect("at least one group")
+                    .push(Operation::Not(Box::new(leaf)));
+                i += 2;
+                continue;
+            }
+            // trailing bare "NOT" with nothing to negate: drop the keyword
+            i += 1;
+            continue;
         }
-    };
-    let id_partial_fuzzy = format!("{}{}{}", "%", id_partial, "%");
 
-    let rows = match stmt.query_map(&[(":id", &id_partial_fuzzy)], |row| {
-        let id_str = match row.get(0) {
-            Ok(v) => v,
-            Err(e) => return Err(e),
-        };
-        Ok(id_str)
-    }) {
-        Ok(v) => v,
-        Err(e) => return Err(SnipError::General(format!("{}", e))),
+        // "a NEAR/n b" fuses the previous bare Query leaf and the next bare word into a
+        // single Near leaf, rather than three separate (and implicitly AND'd) leaves
+        if !negated {
+            if let QueryToken::Word(raw) = tok {
+                if let Some(n) = near_distance(raw) {
+                    let prev_is_bare_query = matches!(
+                        groups.last().and_then(|g| g.last()),
+                        Some(Operation::Query(_))
+                    );
+                    let next = tokens.get(i + 1);
+                    let next_is_bare_word = matches!(
+                        next,
+                        Some((false, QueryToken::Word(next_raw)))
+                            if next_raw != "OR" && near_distance(next_raw).is_none()
+                    );
+                    if prev_is_bare_query && next_is_bare_word {
+                        let a = match groups.last_mut().expect("at least one group").pop() {
+                            Some(Operation::Query(term)) => term,
+                            _ => unreachable!("checked by prev_is_bare_query"),
+                        };
+                        let b = match next {
+                            Some((_, QueryToken::Word(w))) => stem_term(w),
+                            _ => unreachable!("checked by next_is_bare_word"),
+                        };
+                        groups
+                            .last_mut()
+                            .expect("at least one group")
+                            .push(Operation::Near(a, b, n));
+                        i += 2;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        let leaf = match tok {
+            QueryToken::Phrase(raw) => {
+                Operation::Phrase(raw.split_whitespace().map(stem_term).collect())
+            }
+            QueryToken::Group(raw) => parse_query(raw),
+            QueryToken::Word(raw) if Some(i) == last_word_idx => Operation::Prefix(raw.clone()),
+            QueryToken::Word(raw) => Operation::Query(stem_term(raw)),
+        };
+        let leaf = if *negated { Operation::Not(Box::new(leaf)) } else { leaf };
+        groups.last_mut().expect("at least one group").push(leaf);
+        i += 1;
+    }
+
+    let mut or_terms: Vec<Operation> = groups
+        .into_iter()
+        .filter(|g| !g.is_empty())
+        .map(|mut g| {
+            if g.len() > 1 {
+                let filtered: Vec<Operation> = g
+                    .iter()
+                    .filter(|leaf| !matches!(leaf, Operation::Query(term) if is_stop_word(term)))
+                    .cloned()
+                    .collect();
+                if !filtered.is_empty() {
+                    g = filtered;
+                }
+            }
+            if g.len() == 1 {
+                g.remove(0)
+            } else {
+                Operation::And(g)
+            }
+        })
+        .collect();
+
+    match or_terms.len() {
+        0 => Operation::And(Vec::new()),
+        1 => or_terms.remove(0),
+        _ => Operation::Or(or_terms),
+    }
+}
+
+/// Splits a query string into `(negated, token)` pairs: a `"quoted phrase"` becomes a
+/// single `QueryToken::Phrase`, a balanced `(parenthesized group)` becomes a single
+/// `QueryToken::Group` holding its raw inner text (nested parens and quotes inside are
+/// tracked so the matching close paren is found correctly), and a leading `-` (before the
+/// word, the opening quote, or the opening paren) negates that token.
+fn tokenize_query(input: &str) -> Vec<(bool, QueryToken)> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut negated = false;
+        if c == '-' {
+            negated = true;
+            chars.next();
+        }
+
+        if chars.peek() == Some(&'"') {
+            chars.next(); // consume opening quote
+            let mut phrase = String::new();
+            for c in chars.by_ref() {
+                if c == '"' {
+                    break;
+                }
+                phrase.push(c);
+            }
+            if !phrase.is_empty() {
+                tokens.push((negated, QueryToken::Phrase(phrase)));
+            }
+        } else if chars.peek() == Some(&'(') {
+            chars.next(); // consume opening paren
+            let mut depth = 1;
+            let mut inner = String::new();
+            let mut in_quote = false;
+            for c in chars.by_ref() {
+                match c {
+                    '"' => in_quote = !in_quote,
+                    '(' if !in_quote => depth += 1,
+                    ')' if !in_quote => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+                inner.push(c);
+            }
+            tokens.push((negated, QueryToken::Group(inner)));
+        } else if chars.peek() == Some(&')') {
+            chars.next(); // skip a stray/unmatched closing paren
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '(' || c == ')' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            if !word.is_empty() {
+                tokens.push((negated, QueryToken::Word(word)));
+            }
+        }
+    }
+    tokens
+}
+
+/// Evaluates a parsed query tree against `snip_index_rs`, resolving each leaf to its set
+/// of document uuids then combining sets per node: intersection for `And`, union for
+/// `Or`. A `Not` leaf subtracts from the rest of its `And` group, or, standalone or at
+/// the top level, from the whole corpus. Internally backed by a throwaway `QueryContext`
+/// so a single evaluation never reads the same term twice.
+pub fn evaluate_operation(conn: &Connection, op: &Operation) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+    let ctx = QueryContext::new(conn);
+    evaluate_operation_cached(&ctx, op)
+}
+
+/// Parses `input` with `parse_query` and evaluates the resulting tree in one call,
+/// returning the candidate uuid universe a caller can then rank (with `rank_results`,
+/// `rank_by_proximity`, `run_pipeline`, or a bare sort on a secondary signal).
+pub fn search_query(conn: &Connection, input: &str) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+    evaluate_operation(conn, &parse_query(input))
+}
+
+/// Lowers `search_query`'s flat `terms_include`/`terms_exclude` lists into the equivalent
+/// `Operation` tree: every include term is an `And`ed `Query` leaf, and every exclude term
+/// is folded into the same `And` as a `Not`ted sibling. Evaluating the result with
+/// `evaluate_operation` yields the same candidate set `search_structured` resolves for the
+/// flat form under `SearchMethod::IndexStem` with no `prefix_distance` and at least one
+/// include term. With no include terms the two differ: `search_structured` returns
+/// nothing, while the tree subtracts the exclude terms from the whole corpus. `Query`
+/// leaves also pick up registered synonyms, which `search_structured` does not consult.
+pub fn operation_from_search_query(search_query: &SearchQuery) -> Operation {
+    let mut clauses: Vec<Operation> = search_query
+        .terms_include
+        .iter()
+        .cloned()
+        .map(Operation::Query)
+        .collect();
+    clauses.extend(
+        search_query
+            .terms_exclude
+            .iter()
+            .cloned()
+            .map(|term| Operation::Not(Box::new(Operation::Query(term)))),
+    );
+    Operation::And(clauses)
+}
+
+/// Runs a full boolean query string (parentheses, `AND`/`OR`/`NOT`, quoted phrases,
+/// `NEAR/n`) through `parse_query`/`evaluate_operation` and packages the candidate set the
+/// same way `search_structured` does, so the CLI's `search` subcommand can surface a
+/// boolean query through the same summary/excerpt code as a flat include/exclude query.
+/// `matches` is populated with positions for every non-negated leaf term (see
+/// `positive_leaf_terms`) that occurs in the document under its own text — a term under a
+/// `Not` disqualifies rather than matches, so it never appears. A `Prefix` leaf's
+/// completions and a `Query` leaf's synonyms are not looked up, so a document that matched
+/// only through one of them has no entry for that leaf.
+pub fn search_boolean_query(conn: &Connection, input: &str) -> Result<SearchQueryResult, Box<dyn Error>> {
+    let op = parse_query(input);
+    let mut uuids: Vec<Uuid> = evaluate_operation(conn, &op)?.into_iter().collect();
+    uuids.sort_unstable();
+    let terms = positive_leaf_terms(&op);
+
+    let mut query_result = SearchQueryResult { items: Vec::new() };
+    for uuid in uuids {
+        let mut item = SearchQueryItem {
+            uuid,
+            score: None,
+            matches: HashMap::new(),
+        };
+        for term in &terms {
+            let positions = get_term_positions(conn, &uuid, term)?;
+            if !positions.is_empty() {
+                item.matches.insert(term.clone(), positions);
+            }
+        }
+        query_result.items.push(item);
+    }
+    score_search_query(conn, &mut query_result)?;
+    Ok(query_result)
+}
+
+/// Collects every leaf term `op` references that isn't wrapped in an odd number of
+/// `Not`s — a `Phrase` leaf contributes each of its words, a `Near` leaf contributes both
+/// of its terms — sorted and deduplicated. Used to populate `SearchQueryItem::matches`
+/// after a boolean query resolves its candidate set, and to drive the CLI's term summary
+/// display for `--bool` queries.
+pub fn positive_leaf_terms(op: &Operation) -> Vec<String> {
+    fn walk(op: &Operation, negated: bool, out: &mut Vec<String>) {
+        match op {
+            Operation::Query(term) | Operation::Prefix(term) => {
+                if !negated {
+                    out.push(term.clone());
+                }
+            }
+            Operation::Phrase(terms) => {
+                if !negated {
+                    out.extend(terms.iter().cloned());
+                }
+            }
+            Operation::Near(a, b, _) => {
+                if !negated {
+                    out.push(a.clone());
+                    out.push(b.clone());
+                }
+            }
+            Operation::Not(inner) => walk(inner, !negated, out),
+            Operation::And(operations) | Operation::Or(operations) => {
+                for inner in operations {
+                    walk(inner, negated, out);
+                }
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    walk(op, false, &mut out);
+    out.sort_unstable();
+    out.dedup();
+    out
+}
+
+/// Resolves a phrase leaf: intersects the candidate documents for each term, then keeps
+/// only those where the terms' stored positions are consecutive for some alignment.
+fn evaluate_phrase(conn: &Connection, terms: &[String]) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+    if terms.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let mut candidates: Option<HashSet<Uuid>> = None;
+    for term in terms {
+        let set: HashSet<Uuid> = search_uuids_matching_term(conn, term)?.into_iter().collect();
+        candidates = Some(match candidates {
+            Some(mut acc) => {
+                acc.retain(|id| set.contains(id));
+                acc
+            }
+            None => set,
+        });
+    }
+    let candidates = candidates.unwrap_or_default();
+
+    let mut matches = HashSet::new();
+    for uuid in candidates {
+        let mut positions_per_term: Vec<Vec<usize>> = Vec::new();
+        for term in terms {
+            positions_per_term.push(get_term_positions(conn, &uuid, term)?);
+        }
+        if phrase_positions_adjacent(&positions_per_term) {
+            matches.insert(uuid);
+        }
+    }
+    Ok(matches)
+}
+
+/// Resolves a `Near(a, b, max_gap)` leaf: the documents containing both `a` and `b` where
+/// `near_anchor_positions` finds some pair of occurrences within `max_gap` of each other.
+fn evaluate_near(conn: &Connection, a: &str, b: &str, max_gap: u64) -> Result<HashSet<Uuid>, Box<dyn Error>> {
+    let set_a: HashSet<Uuid> = search_uuids_matching_term(conn, &a.to_string())?.into_iter().collect();
+    let set_b: HashSet<Uuid> = search_uuids_matching_term(conn, &b.to_string())?.into_iter().collect();
+
+    let mut matches = HashSet::new();
+    for uuid in set_a.intersection(&set_b) {
+        if near_anchor_positions(conn, uuid, a, b, max_gap)?.is_some() {
+            matches.insert(*uuid);
+        }
+    }
+    Ok(matches)
+}
+
+/// Finds the closest pair of occurrences of `a` and `b` in `uuid`, returning their
+/// `(pos_a, pos_b)` positions if some pair is within `max_gap` of each other. Walks both
+/// sorted position lists with a single two-pointer merge (the same linear technique
+/// `min_term_span` uses for more than two terms) rather than comparing every pair.
+pub fn near_anchor_positions(
+    conn: &Connection,
+    uuid: &Uuid,
+    a: &str,
+    b: &str,
+    max_gap: u64,
+) -> Result<Option<(usize, usize)>, Box<dyn Error>> {
+    let mut positions_a = get_term_positions(conn, uuid, &a.to_string())?;
+    let mut positions_b = get_term_positions(conn, uuid, &b.to_string())?;
+    positions_a.sort_unstable();
+    positions_b.sort_unstable();
+
+    let mut best: Option<(usize, usize)> = None;
+    let (mut i, mut j) = (0, 0);
+    while i < positions_a.len() && j < positions_b.len() {
+        let (pa, pb) = (positions_a[i], positions_b[j]);
+        let gap = pa.abs_diff(pb);
+        if gap as u64 <= max_gap {
+            let better = best.map_or(true, |(best_a, best_b)| gap < best_a.abs_diff(best_b));
+            if better {
+                best = Some((pa, pb));
+            }
+        }
+        if pa < pb {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    Ok(best)
+}
+
+/// Returns the earliest starting position of a document where a phrase's terms occur
+/// consecutively, for anchoring an excerpt on the whole phrase match (e.g. via
+/// `SnipAnalysis::get_best_excerpt`) rather than a single term within it.
+pub fn phrase_anchor_position(
+    conn: &Connection,
+    uuid: &Uuid,
+    terms: &[String],
+) -> Result<Option<usize>, Box<dyn Error>> {
+    let positions_per_term: Vec<Vec<usize>> = terms
+        .iter()
+        .map(|term| get_term_positions(conn, uuid, term))
+        .collect::<Result<_, _>>()?;
+    Ok(phrase_anchor_start(&positions_per_term))
+}
+
+/// Searches for an ordered chain of terms where each occurs within `max_gap` token
+/// positions after the previous one. `max_gap == 1` gives exact adjacent phrase
+/// matching (equivalent to a `Phrase` leaf); larger gaps give "near" queries. Returns,
+/// for each matching snip, the earliest starting position of the chain.
+pub fn search_phrase(
+    conn: &Connection,
+    terms: &[&str],
+    max_gap: u64,
+) -> Result<Vec<(Uuid, u64)>, Box<dyn Error>> {
+    if terms.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut candidates: Option<HashSet<Uuid>> = None;
+    for term in terms {
+        let set: HashSet<Uuid> = search_uuids_matching_term(conn, &term.to_string())?
+            .into_iter()
+            .collect();
+        candidates = Some(match candidates {
+            Some(mut acc) => {
+                acc.retain(|id| set.contains(id));
+                acc
+            }
+            None => set,
+        });
+    }
+    let candidates = candidates.unwrap_or_default();
+
+    let mut matches = Vec::new();
+    for uuid in candidates {
+        let positions_per_term: Vec<Vec<u64>> = terms
+            .iter()
+            .map(|term| -> Result<Vec<u64>, Box<dyn Error>> {
+                Ok(get_term_positions(conn, &uuid, &term.to_string())?
+                    .into_iter()
+                    .map(|p| p as u64)
+                    .collect())
+            })
+            .collect::<Result<_, _>>()?;
+
+        if let Some(start) = phrase_chain_start(&positions_per_term, max_gap) {
+            matches.push((uuid, start));
+        }
+    }
+    Ok(matches)
+}
+
+/// Returns the earliest position in `positions_per_term[0]` from which a chain exists
+/// where every subsequent term's position falls within `(prev, prev + max_gap]`, or
+/// `None` if no such chain exists.
+fn phrase_chain_start(positions_per_term: &[Vec<u64>], max_gap: u64) -> Option<u64> {
+    let first = positions_per_term.first()?;
+
+    'start: for &start in first {
+        let mut prev = start;
+        for positions in &positions_per_term[1..] {
+            match positions.iter().find(|&&p| p > prev && p <= prev + max_gap) {
+                Some(&p) => prev = p,
+                None => continue 'start,
+            }
+        }
+        return Some(start);
+    }
+    None
+}
+
+/// Like `search_phrase`, but takes a raw phrase string (tokenized and stemmed
+/// internally) and a `slop` budget, and reports the *tightest* ordered match per
+/// document — the occurrence with the smallest total span — rather than just the first
+/// chain found scanning from the earliest starting position. Returns `(uuid, start,
+/// span)` triples so callers can highlight the most relevant occurrence.
+pub fn search_phrase_str(
+    conn: &Connection,
+    phrase: &str,
+    slop: u32,
+) -> Result<Vec<(Uuid, usize, usize)>, Box<dyn Error>> {
+    let terms: Vec<String> = phrase.split_whitespace().map(stem_term).collect();
+    if terms.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut candidates: Option<HashSet<Uuid>> = None;
+    for term in &terms {
+        let set: HashSet<Uuid> = search_uuids_matching_term(conn, term)?.into_iter().collect();
+        candidates = Some(match candidates {
+            Some(mut acc) => {
+                acc.retain(|id| set.contains(id));
+                acc
+            }
+            None => set,
+        });
+    }
+    let candidates = candidates.unwrap_or_default();
+
+    let mut matches = Vec::new();
+    for uuid in candidates {
+        let positions_per_term: Vec<Vec<usize>> = terms
+            .iter()
+            .map(|term| get_term_positions(conn, &uuid, term))
+            .collect::<Result<_, _>>()?;
+
+        if let Some((start, span)) = tightest_ordered_span(&positions_per_term, slop as usize) {
+            matches.push((uuid, start, span));
+        }
+    }
+    Ok(matches)
+}
+
+/// Scans every starting position in the first term's positions for an ordered chain
+/// (each subsequent term within `slop` positions after the previous one), keeping the
+/// chain with the smallest total span instead of stopping at the first one found.
+fn tightest_ordered_span(positions_per_term: &[Vec<usize>], slop: usize) -> Option<(usize, usize)> {
+    let first = positions_per_term.first()?;
+    let mut best: Option<(usize, usize)> = None;
+
+    for &start in first {
+        let mut prev = start;
+        let mut ok = true;
+        for positions in &positions_per_term[1..] {
+            match positions.iter().find(|&&p| p > prev && p <= prev + slop + 1) {
+                Some(&p) => prev = p,
+                None => {
+                    ok = false;
+                    break;
+                }
+            }
+        }
+        if ok {
+            let span = prev - start;
+            if best.map_or(true, |(_, best_span)| span < best_span) {
+                best = Some((start, span));
+            }
+        }
+    }
+    best
+}
+
+/// Controls how much edit-distance budget a query word is given based on its length.
+#[derive(Debug, Clone, Copy)]
+pub enum TypoPolicy {
+    /// Mirrors the tolerant-search thresholds used elsewhere: 0 edits for len<=3, 1 for
+    /// len<=7, 2 otherwise.
+    Standard,
+    /// 0 edits for len<=4, 1 edit for len 5-8, 2 edits for longer.
+    LengthScaled,
+}
+
+impl TypoPolicy {
+    pub fn max_distance(&self, len: usize) -> usize {
+        match self {
+            TypoPolicy::Standard => max_typo_distance(len),
+            TypoPolicy::LengthScaled => match len {
+                0..=4 => 0,
+                5..=8 => 1,
+                _ => 2,
+            },
+        }
+    }
+}
+
+/// Resolves `query` against the indexed terms under `policy`'s edit-distance budget,
+/// returning matching uuids grouped by the distance that produced the hit (ascending),
+/// so callers can prefer exact hits, then distance-1, then distance-2.
+pub fn search_index_fuzzy(
+    conn: &Connection,
+    query: &str,
+    policy: TypoPolicy,
+) -> Result<Vec<(usize, Vec<Uuid>)>, Box<dyn Error>> {
+    let max_distance = policy.max_distance(query.chars().count());
+
+    let mut by_distance: HashMap<usize, Vec<Uuid>> = HashMap::new();
+    for (term, distance) in fuzzy_term_candidates(conn, query, max_distance, false)? {
+        for uuid in search_uuids_matching_term(conn, &term)? {
+            by_distance.entry(distance).or_default().push(uuid);
+        }
+    }
+
+    let mut grouped: Vec<(usize, Vec<Uuid>)> = by_distance.into_iter().collect();
+    grouped.sort_by_key(|(distance, _)| *distance);
+    Ok(grouped)
+}
+
+/// Returns indexed terms beginning with `prefix` (case-insensitively) along with their
+/// aggregated document frequency, ordered most-common first and capped at `limit` —
+/// suitable for driving CLI/REPL type-ahead completion. The term returned preserves its
+/// originally stored casing even though the match itself is case-folded.
+pub fn complete_term(
+    conn: &Connection,
+    prefix: &str,
+    limit: usize,
+) -> Result<Vec<(String, u64)>, Box<dyn Error>> {
+    let pattern = format!("{}%", prefix.to_lowercase());
+    let mut stmt = conn.prepare(
+        "SELECT term, COUNT(DISTINCT uuid) AS df FROM snip_index_rs \
+         WHERE LOWER(term) LIKE :prefix \
+         GROUP BY term ORDER BY df DESC LIMIT :limit",
+    )?;
+    let rows = stmt.query_and_then(
+        rusqlite::named_params! { ":prefix": pattern, ":limit": limit as i64 },
+        |row| -> Result<(String, u64), Box<dyn Error>> {
+            let term: String = row.get(0)?;
+            let df: i64 = row.get(1)?;
+            Ok((term, df as u64))
+        },
+    )?;
+
+    let mut completions = Vec::new();
+    for row in rows {
+        completions.push(row?);
+    }
+    Ok(completions)
+}
+
+/// Returns true if there is some starting position in the first term's positions such
+/// that every subsequent term occurs exactly one position after the previous one.
+fn phrase_positions_adjacent(positions_per_term: &[Vec<usize>]) -> bool {
+    phrase_anchor_start(positions_per_term).is_some()
+}
+
+/// Returns the earliest starting position where every term in `positions_per_term` occurs
+/// at consecutive offsets from it (i.e. where the phrase they spell out actually appears).
+fn phrase_anchor_start(positions_per_term: &[Vec<usize>]) -> Option<usize> {
+    if positions_per_term.is_empty() {
+        return None;
+    }
+
+    for &start in &positions_per_term[0] {
+        let aligned = positions_per_term
+            .iter()
+            .enumerate()
+            .skip(1)
+            .all(|(offset, positions)| positions.contains(&(start + offset)));
+        if aligned {
+            return Some(start);
+        }
+    }
+    None
+}
+
+/// Finds the smallest span `max_pos - min_pos` of a window that contains at least one
+/// occurrence of every term, by merging all (position, term index) pairs into one sorted
+/// list and sliding a window across it. Returns `None` if any term has no positions at
+/// all, since no window could then cover every term.
+pub fn min_term_span(positions_per_term: &[Vec<usize>]) -> Option<usize> {
+    let num_terms = positions_per_term.len();
+    if num_terms == 0 || positions_per_term.iter().any(|p| p.is_empty()) {
+        return None;
+    }
+
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (term_idx, positions) in positions_per_term.iter().enumerate() {
+        for &pos in positions {
+            merged.push((pos, term_idx));
+        }
+    }
+    merged.sort_unstable();
+
+    let mut counts = vec![0usize; num_terms];
+    let mut distinct = 0;
+    let mut left = 0;
+    let mut best: Option<usize> = None;
+
+    for right in 0..merged.len() {
+        let (pos_r, term_r) = merged[right];
+        if counts[term_r] == 0 {
+            distinct += 1;
+        }
+        counts[term_r] += 1;
+
+        while distinct == num_terms {
+            let (pos_l, term_l) = merged[left];
+            let span = pos_r - pos_l;
+            best = Some(best.map_or(span, |b| b.min(span)));
+
+            counts[term_l] -= 1;
+            if counts[term_l] == 0 {
+                distinct -= 1;
+            }
+            left += 1;
+        }
+    }
+    best
+}
+
+/// Converts a minimum term span into a proximity score where a smaller span (terms
+/// clustered tightly together) ranks higher. Documents where the terms never co-occur
+/// (`span` is `None`) get the lowest possible score rather than being excluded.
+pub fn proximity_score(span: Option<usize>) -> f64 {
+    match span {
+        Some(span) => 1.0 / (1.0 + span as f64),
+        None => 0.0,
+    }
+}
+
+/// Ranks `uuids` by how tightly `terms` cluster within each document, highest proximity
+/// score first.
+pub fn rank_by_proximity(
+    conn: &Connection,
+    uuids: Vec<Uuid>,
+    terms: &[String],
+) -> Result<Vec<(Uuid, f64)>, Box<dyn Error>> {
+    let mut scored: Vec<(Uuid, f64)> = Vec::new();
+    for uuid in uuids {
+        let positions_per_term: Vec<Vec<usize>> = terms
+            .iter()
+            .map(|term| get_term_positions(conn, &uuid, term))
+            .collect::<Result<_, _>>()?;
+        let score = proximity_score(min_term_span(&positions_per_term));
+        scored.push((uuid, score));
+    }
+    scored.sort_by(|a, b| b.1.partial_cmp(&a.1).expect("proximity scores are finite"));
+    Ok(scored)
+}
+
+/// Ceiling a pairwise proximity gap clamps to, so two query terms that happen to be very
+/// far apart in a document collapse into the same lowest-priority bucket rather than
+/// spreading arbitrarily large gaps across many distinct ranks.
+const PROXIMITY_DISTANCE_CEILING: u64 = 8;
+
+/// Computes a multi-term proximity distance for `uuid`: for each adjacent pair of
+/// `terms` (in query order), the minimum pairwise word-distance via the same two-pointer
+/// merge `near_anchor_positions` uses for a `NEAR` query, clamped to
+/// `PROXIMITY_DISTANCE_CEILING` and summed across the query. A pair sharing no positions
+/// at all (or a query of fewer than two terms) contributes the ceiling / zero
+/// respectively, rather than failing the whole computation.
+pub fn proximity_distance(conn: &Connection, uuid: &Uuid, terms: &[String]) -> Result<u64, Box<dyn Error>> {
+    if terms.len() < 2 {
+        return Ok(0);
+    }
+
+    let mut total = 0u64;
+    for pair in terms.windows(2) {
+        let gap = near_anchor_positions(conn, uuid, &pair[0], &pair[1], u64::MAX)?
+            .map(|(a, b)| a.abs_diff(b) as u64)
+            .unwrap_or(PROXIMITY_DISTANCE_CEILING);
+        total += gap.min(PROXIMITY_DISTANCE_CEILING);
+    }
+    Ok(total)
+}
+
+/// Ranks `uuids` by `proximity_distance` ascending (tightest query-ordered clustering
+/// first), breaking ties by how many of `terms` a document actually contains (more
+/// matched terms first) — a companion to `rank_by_proximity`'s covering-span metric, for
+/// callers that want a query-ordered pairwise distance instead of a single window size.
+pub fn rank_by_proximity_distance(
+    conn: &Connection,
+    uuids: Vec<Uuid>,
+    terms: &[String],
+) -> Result<Vec<(Uuid, u64)>, Box<dyn Error>> {
+    let mut scored: Vec<(Uuid, u64, usize)> = Vec::new();
+    for uuid in uuids {
+        let distance = proximity_distance(conn, &uuid, terms)?;
+        let mut matched = 0;
+        for term in terms {
+            if !get_term_positions(conn, &uuid, term)?.is_empty() {
+                matched += 1;
+            }
+        }
+        scored.push((uuid, distance, matched));
+    }
+    scored.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| b.2.cmp(&a.2)));
+    Ok(scored
+        .into_iter()
+        .map(|(uuid, distance, _)| (uuid, distance))
+        .collect())
+}
+
+/// Standard BM25 term-frequency saturation parameter.
+const BM25_K1: f64 = 1.2;
+/// Standard BM25 document-length normalization parameter.
+const BM25_B: f64 = 0.75;
+
+/// Number of distinct documents containing `term` in the index. The stats behind BM25
+/// (document frequency, document length, corpus average) are derived on the fly from
+/// `snip_index_rs` rather than persisted, since they are cheap aggregate queries over a
+/// table that is already the source of truth — keeping them in sync on every index
+/// update would otherwise duplicate that bookkeeping.
+fn document_frequency(conn: &Connection, term: &str) -> Result<u64, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT COUNT(DISTINCT uuid) FROM snip_index_rs WHERE term = :term")?;
+    let df: i64 = stmt.query_row(&[(":term", &term)], |row| row.get(0))?;
+    Ok(df as u64)
+}
+
+/// Total indexed word occurrences for a single document. Reads the value `Snip::index`
+/// caches in `snip_doc_len` rather than re-summing `snip_index_rs`; falls back to a live
+/// sum for documents indexed before that cache existed.
+fn document_length(conn: &Connection, uuid: &Uuid) -> Result<u64, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT length FROM snip_doc_len WHERE uuid = :uuid")?;
+    let cached: Option<i64> = stmt
+        .query_row(&[(":uuid", &uuid.to_string())], |row| row.get(0))
+        .optional()?;
+    if let Some(len) = cached {
+        return Ok(len as u64);
+    }
+
+    let mut stmt =
+        conn.prepare("SELECT COALESCE(SUM(count), 0) FROM snip_index_rs WHERE uuid = :uuid")?;
+    let len: i64 = stmt.query_row(&[(":uuid", &uuid.to_string())], |row| row.get(0))?;
+    Ok(len as u64)
+}
+
+/// Number of indexed documents and their average length, used as BM25's corpus-wide
+/// normalization baseline.
+fn corpus_stats(conn: &Connection) -> Result<(u64, f64), Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT COUNT(DISTINCT uuid) FROM snip_index_rs")?;
+    let doc_count: i64 = stmt.query_row([], |row| row.get(0))?;
+
+    let mut stmt = conn.prepare("SELECT COALESCE(SUM(count), 0) FROM snip_index_rs")?;
+    let total_words: i64 = stmt.query_row([], |row| row.get(0))?;
+
+    let doc_count = doc_count as u64;
+    let avg_len = if doc_count == 0 {
+        0.0
+    } else {
+        total_words as f64 / doc_count as f64
     };
+    Ok((doc_count, avg_len))
+}
+
+/// How much more heavily a term match in a document's name counts toward BM25 term
+/// frequency than the same match in its body text, so a document whose name itself
+/// contains the query terms ranks above one that merely mentions them in passing.
+const NAME_FIELD_BOOST: f64 = 3.0;
+
+/// Weighted term frequency of `term` within `uuid`'s document: its `"body"`-field count
+/// plus its `"name"`-field count scaled by `NAME_FIELD_BOOST`, summed across whichever
+/// fields the term actually appears in.
+fn term_frequency(conn: &Connection, uuid: &Uuid, term: &str) -> Result<f64, Box<dyn Error>> {
+    let mut stmt = conn
+        .prepare("SELECT field, count FROM snip_index_rs WHERE uuid = :uuid AND term = :term")?;
+    let rows = stmt.query_and_then(
+        &[(":uuid", &uuid.to_string()), (":term", &term.to_string())],
+        |row| -> Result<(String, i64), rusqlite::Error> { Ok((row.get(0)?, row.get(1)?)) },
+    )?;
+
+    let mut tf = 0.0;
+    for row in rows {
+        let (field, count) = row?;
+        let weight = if field == "name" { NAME_FIELD_BOOST } else { 1.0 };
+        tf += count as f64 * weight;
+    }
+    Ok(tf)
+}
+
+/// Scores a document against a set of query terms using BM25 (k1=1.2, b=0.75), summing
+/// each term's `idf * tf-normalization` contribution.
+pub fn bm25_score(conn: &Connection, uuid: &Uuid, terms: &[String]) -> Result<f64, Box<dyn Error>> {
+    bm25_score_with_params(conn, uuid, terms, BM25_K1, BM25_B)
+}
+
+/// Same as `bm25_score`, but with the `k1`/`b` free parameters exposed rather than
+/// fixed at their conventional defaults.
+pub fn bm25_score_with_params(
+    conn: &Connection,
+    uuid: &Uuid,
+    terms: &[String],
+    k1: f64,
+    b: f64,
+) -> Result<f64, Box<dyn Error>> {
+    let stats = corpus_stats(conn)?;
+    bm25_score_with_stats(conn, uuid, terms, k1, b, stats)
+}
+
+/// Same as `bm25_score_with_params`, but takes an already-computed `(doc_count,
+/// avg_doc_len)` pair rather than querying for it. A single ranking pass scores many
+/// documents against the same corpus, and those two aggregates don't change within the
+/// pass, so callers that loop over candidates (`rank_results`, `search_index_ranked`)
+/// compute `corpus_stats` once up front and pass it to every scoring call instead of
+/// re-querying it per document.
+pub fn bm25_score_with_stats(
+    conn: &Connection,
+    uuid: &Uuid,
+    terms: &[String],
+    k1: f64,
+    b: f64,
+    (doc_count, avg_doc_len): (u64, f64),
+) -> Result<f64, Box<dyn Error>> {
+    if doc_count == 0 || avg_doc_len == 0.0 {
+        return Ok(0.0);
+    }
+    let doc_len = document_length(conn, uuid)? as f64;
+
+    let mut score = 0.0;
+    for term in terms {
+        let tf = term_frequency(conn, uuid, term)?;
+        if tf == 0.0 {
+            continue;
+        }
+        let df = document_frequency(conn, term)? as f64;
+        let idf = ((doc_count as f64 - df + 0.5) / (df + 0.5) + 1.0).ln();
+        let numerator = tf * (k1 + 1.0);
+        let denominator = tf + k1 * (1.0 - b + b * doc_len / avg_doc_len);
+        score += idf * (numerator / denominator);
+    }
+    Ok(score)
+}
+
+/// Returns every snip containing at least one of `query`'s terms, sorted by descending
+/// BM25 relevance across all of them combined. This is the simple full-text entry point
+/// that callers reach for before layering on the typo/proximity/exactness pipeline in
+/// `rank_results`.
+pub fn search_index_ranked(
+    conn: &Connection,
+    query: &[&str],
+) -> Result<Vec<(Uuid, f64)>, Box<dyn Error>> {
+    let terms: Vec<String> = query.iter().map(|t| t.to_string()).collect();
+
+    let mut candidates: HashSet<Uuid> = HashSet::new();
+    for term in &terms {
+        candidates.extend(search_uuids_matching_term(conn, term)?);
+    }
+
+    let stats = corpus_stats(conn)?;
+    let mut scored: Vec<(Uuid, f64)> = candidates
+        .into_iter()
+        .map(|uuid| {
+            bm25_score_with_stats(conn, &uuid, &terms, BM25_K1, BM25_B, stats)
+                .map(|score| (uuid, score))
+        })
+        .collect::<Result<_, _>>()?;
+    scored.sort_by(|a, b| b.1.partial_cmp(&a.1).expect("bm25 scores are finite"));
+    Ok(scored)
+}
+
+/// Tokenizes `query` on whitespace, stems each token, and returns every matching snip
+/// ranked by descending BM25 relevance. This is the plain-string entry point for callers
+/// that just want "rank these documents by how well they match this text" without
+/// building the `&[&str]` term list `search_index_ranked` expects directly.
+pub fn search_ranked(conn: &Connection, query: &str) -> Result<Vec<(Uuid, f64)>, Box<dyn Error>> {
+    let terms: Vec<String> = query.split_whitespace().map(stem_term).collect();
+    let term_refs: Vec<&str> = terms.iter().map(|t| t.as_str()).collect();
+    search_index_ranked(conn, &term_refs)
+}
+
+/// A candidate document carrying everything the ranking pipeline needs to order it
+/// against its peers, mirroring Meilisearch's ordered ranking-rules design: typo count,
+/// then proximity, then exactness, then relevance, each breaking ties in the last.
+#[derive(Debug, Clone)]
+pub struct RankedCandidate {
+    pub uuid: Uuid,
+    pub typo_distance: usize,
+    pub proximity_span: Option<usize>,
+    pub exact: bool,
+    pub bm25: f64,
+}
+
+/// Sorts candidates in place: fewest typo corrections first, then tightest term
+/// proximity, then exact (unstemmed) matches before fuzzy/stemmed ones, then highest
+/// BM25 score — each criterion only breaks ties left by the previous one.
+pub fn rank_candidates(candidates: &mut [RankedCandidate]) {
+    candidates.sort_by(|a, b| {
+        a.typo_distance
+            .cmp(&b.typo_distance)
+            .then_with(|| span_rank(a.proximity_span).cmp(&span_rank(b.proximity_span)))
+            .then_with(|| b.exact.cmp(&a.exact))
+            .then_with(|| {
+                b.bm25
+                    .partial_cmp(&a.bm25)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+    });
+}
+
+/// Spans are compared ascending with documents lacking a window (terms never co-occur)
+/// ranked last.
+fn span_rank(span: Option<usize>) -> usize {
+    span.unwrap_or(usize::MAX)
+}
+
+/// Runs the full ranking pipeline for a set of candidate documents against the terms
+/// that matched the query (as produced by `tolerant_terms`), returning uuids ordered by
+/// the typo/proximity/exactness/BM25 pipeline, paired with their BM25 score. Position
+/// lookups are routed through a throwaway `QueryContext` so a (document, term) pair
+/// already seen for one candidate isn't re-queried for the next, and `corpus_stats` is
+/// computed once up front rather than once per candidate, since it's the same aggregate
+/// for every document in the pass.
+pub fn rank_results(
+    conn: &Connection,
+    uuids: Vec<Uuid>,
+    matched_terms: &[TolerantMatch],
+) -> Result<Vec<(Uuid, f64)>, Box<dyn Error>> {
+    let ctx = QueryContext::new(conn);
+    let terms: Vec<String> = matched_terms.iter().map(|m| m.term.clone()).collect();
+    let typo_distance = matched_terms.iter().map(|m| m.distance).max().unwrap_or(0);
+    let exact = matched_terms.iter().all(|m| m.distance == 0);
+    let stats = corpus_stats(conn)?;
+
+    let mut candidates: Vec<RankedCandidate> = Vec::with_capacity(uuids.len());
+    for uuid in uuids {
+        let positions_per_term: Vec<Vec<usize>> = terms
+            .iter()
+            .map(|term| ctx.term_positions(&uuid, term))
+            .collect::<Result<_, _>>()?;
+        let proximity_span = min_term_span(&positions_per_term);
+        let bm25 = bm25_score_with_stats(conn, &uuid, &terms, BM25_K1, BM25_B, stats)?;
+        candidates.push(RankedCandidate {
+            uuid,
+            typo_distance,
+            proximity_span,
+            exact,
+            bm25,
+        });
+    }
+
+    rank_candidates(&mut candidates);
+    Ok(candidates.into_iter().map(|c| (c.uuid, c.bm25)).collect())
+}
+
+/// The set of documents still in contention partway through a `Criterion` pipeline, along
+/// with the query context each criterion needs to score them: the stemmed terms used for
+/// positional lookups, and the original (unstemmed) words aligned with them so a criterion
+/// like `ExactnessCriterion` can tell an exact match from a stem-only one.
+#[derive(Debug, Clone)]
+pub struct CandidateSet {
+    pub uuids: Vec<Uuid>,
+    pub query_terms: Vec<String>,
+    pub raw_terms: Vec<String>,
+}
+
+/// One ordered slice of a criterion's output: documents tied at this criterion's rank,
+/// together with the score that justified grouping them here (so callers can show why a
+/// result landed where it did).
+#[derive(Debug, Clone)]
+pub struct RankedBucket {
+    pub uuids: Vec<Uuid>,
+    pub score: f64,
+}
+
+/// A single stage of the ranking-rule pipeline, modeled on Meilisearch's ordered
+/// criteria: each call to `next` partitions the documents still in `candidates` and
+/// returns the best-ranked slice, removing it from `candidates` so the following call
+/// (or the next criterion in the pipeline) only sees what's left. Returns `None` once
+/// `candidates` is exhausted.
+pub trait Criterion {
+    /// Short label used in a `ScoredUuid`'s score breakdown.
+    fn name(&self) -> &'static str;
+    fn next(&mut self, candidates: &mut CandidateSet) -> Option<RankedBucket>;
+}
+
+/// A document's final position in a `run_pipeline` result, carrying the
+/// `(criterion name, score)` pairs that placed it there, in pipeline order.
+#[derive(Debug, Clone)]
+pub struct ScoredUuid {
+    pub uuid: Uuid,
+    pub breakdown: Vec<(String, f64)>,
+}
+
+/// Ranks `candidates` through a fixed sequence of `Criterion`s: the first criterion
+/// divides all candidates into ordered buckets, then each subsequent criterion only
+/// re-orders the documents *within* a bucket left by the one before it, so a later
+/// criterion can only break ties, never overturn an earlier one's ranking.
+pub fn run_pipeline<'a>(
+    candidates: &CandidateSet,
+    mut criteria: Vec<Box<dyn Criterion + 'a>>,
+) -> Vec<ScoredUuid> {
+    let mut groups: Vec<Vec<Uuid>> = vec![candidates.uuids.clone()];
+    let mut breakdown: HashMap<Uuid, Vec<(String, f64)>> = HashMap::new();
+
+    for criterion in criteria.iter_mut() {
+        let mut next_groups: Vec<Vec<Uuid>> = Vec::new();
+        for group in groups {
+            let mut remaining = CandidateSet {
+                uuids: group,
+                query_terms: candidates.query_terms.clone(),
+                raw_terms: candidates.raw_terms.clone(),
+            };
+            while let Some(bucket) = criterion.next(&mut remaining) {
+                for &uuid in &bucket.uuids {
+                    breakdown
+                        .entry(uuid)
+                        .or_default()
+                        .push((criterion.name().to_string(), bucket.score));
+                }
+                next_groups.push(bucket.uuids);
+            }
+        }
+        groups = next_groups;
+    }
+
+    groups
+        .into_iter()
+        .flatten()
+        .map(|uuid| ScoredUuid {
+            breakdown: breakdown.remove(&uuid).unwrap_or_default(),
+            uuid,
+        })
+        .collect()
+}
+
+/// Buckets candidates by how many distinct query terms they contain, most first —
+/// documents missing a term behave as if that term had been optionally dropped, falling
+/// into a lower bucket rather than being excluded outright.
+pub struct WordsCriterion<'conn> {
+    conn: &'conn Connection,
+}
+
+impl<'conn> WordsCriterion<'conn> {
+    pub fn new(conn: &'conn Connection) -> Self {
+        WordsCriterion { conn }
+    }
+}
+
+impl<'conn> Criterion for WordsCriterion<'conn> {
+    fn name(&self) -> &'static str {
+        "words"
+    }
+
+    fn next(&mut self, candidates: &mut CandidateSet) -> Option<RankedBucket> {
+        if candidates.uuids.is_empty() {
+            return None;
+        }
+        let mut by_count: HashMap<usize, Vec<Uuid>> = HashMap::new();
+        for &uuid in &candidates.uuids {
+            let count = candidates
+                .query_terms
+                .iter()
+                .filter(|term| {
+                    get_term_positions(self.conn, &uuid, term)
+                        .map(|p| !p.is_empty())
+                        .unwrap_or(false)
+                })
+                .count();
+            by_count.entry(count).or_default().push(uuid);
+        }
+        let best = *by_count.keys().max()?;
+        let bucket_uuids = by_count.remove(&best).expect("key came from this map");
+        candidates.uuids.retain(|u| !bucket_uuids.contains(u));
+        Some(RankedBucket {
+            uuids: bucket_uuids,
+            score: best as f64,
+        })
+    }
+}
+
+/// Buckets candidates by total edit-distance errors across their matched query terms,
+/// fewest first, reusing the derivation lists a `WordDerivationsCache` already computed
+/// for fuzzy search rather than re-deriving them per document.
+pub struct TypoCriterion<'conn> {
+    conn: &'conn Connection,
+    derivations: Vec<Vec<(String, u8)>>,
+}
+
+impl<'conn> TypoCriterion<'conn> {
+    pub fn new(
+        conn: &'conn Connection,
+        cache: &mut WordDerivationsCache<'conn>,
+        query_terms: &[String],
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut derivations = Vec::with_capacity(query_terms.len());
+        for term in query_terms {
+            derivations.push(cache.derivations(term, false)?);
+        }
+        Ok(TypoCriterion { conn, derivations })
+    }
+
+    fn typo_total(&self, uuid: &Uuid) -> usize {
+        self.derivations
+            .iter()
+            .map(|accepted| {
+                accepted
+                    .iter()
+                    .filter(|(term, _)| {
+                        get_term_positions(self.conn, uuid, term)
+                            .map(|p| !p.is_empty())
+                            .unwrap_or(false)
+                    })
+                    .map(|(_, distance)| *distance as usize)
+                    .min()
+                    // a term present in none of its accepted derivations is worse than
+                    // any derivation this query term actually accepted
+                    .unwrap_or(3)
+            })
+            .sum()
+    }
+}
+
+impl<'conn> Criterion for TypoCriterion<'conn> {
+    fn name(&self) -> &'static str {
+        "typo"
+    }
+
+    fn next(&mut self, candidates: &mut CandidateSet) -> Option<RankedBucket> {
+        if candidates.uuids.is_empty() {
+            return None;
+        }
+        let mut by_typo: HashMap<usize, Vec<Uuid>> = HashMap::new();
+        for &uuid in &candidates.uuids {
+            by_typo.entry(self.typo_total(&uuid)).or_default().push(uuid);
+        }
+        let best = *by_typo.keys().min()?;
+        let bucket_uuids = by_typo.remove(&best).expect("key came from this map");
+        candidates.uuids.retain(|u| !bucket_uuids.contains(u));
+        Some(RankedBucket {
+            uuids: bucket_uuids,
+            score: best as f64,
+        })
+    }
+}
+
+/// Buckets candidates by how tightly their query terms cluster (`min_term_span`),
+/// tightest first.
+pub struct ProximityCriterion<'conn> {
+    conn: &'conn Connection,
+}
+
+impl<'conn> ProximityCriterion<'conn> {
+    pub fn new(conn: &'conn Connection) -> Self {
+        ProximityCriterion { conn }
+    }
+}
+
+impl<'conn> Criterion for ProximityCriterion<'conn> {
+    fn name(&self) -> &'static str {
+        "proximity"
+    }
+
+    fn next(&mut self, candidates: &mut CandidateSet) -> Option<RankedBucket> {
+        if candidates.uuids.is_empty() {
+            return None;
+        }
+        let mut by_span: HashMap<usize, Vec<Uuid>> = HashMap::new();
+        for &uuid in &candidates.uuids {
+            let positions_per_term: Vec<Vec<usize>> = candidates
+                .query_terms
+                .iter()
+                .map(|term| get_term_positions(self.conn, &uuid, term).unwrap_or_default())
+                .collect();
+            let span = span_rank(min_term_span(&positions_per_term));
+            by_span.entry(span).or_default().push(uuid);
+        }
+        let best = *by_span.keys().min()?;
+        let bucket_uuids = by_span.remove(&best).expect("key came from this map");
+        candidates.uuids.retain(|u| !bucket_uuids.contains(u));
+        Some(RankedBucket {
+            uuids: bucket_uuids,
+            score: proximity_score(if best == usize::MAX { None } else { Some(best) }),
+        })
+    }
+}
+
+/// Buckets candidates into an exact-match group (every query word appears in the
+/// document unstemmed) ranked ahead of a stem-only-match group.
+pub struct ExactnessCriterion<'conn> {
+    conn: &'conn Connection,
+}
+
+impl<'conn> ExactnessCriterion<'conn> {
+    pub fn new(conn: &'conn Connection) -> Self {
+        ExactnessCriterion { conn }
+    }
+}
+
+impl<'conn> Criterion for ExactnessCriterion<'conn> {
+    fn name(&self) -> &'static str {
+        "exactness"
+    }
+
+    fn next(&mut self, candidates: &mut CandidateSet) -> Option<RankedBucket> {
+        if candidates.uuids.is_empty() {
+            return None;
+        }
+        let (mut exact, mut stemmed) = (Vec::new(), Vec::new());
+        for &uuid in &candidates.uuids {
+            let is_exact = candidates
+                .raw_terms
+                .iter()
+                .zip(&candidates.query_terms)
+                .all(|(raw, stem)| {
+                    raw == stem
+                        && get_term_positions(self.conn, &uuid, stem)
+                            .map(|p| !p.is_empty())
+                            .unwrap_or(false)
+                });
+            if is_exact {
+                exact.push(uuid);
+            } else {
+                stemmed.push(uuid);
+            }
+        }
+        if !exact.is_empty() {
+            candidates.uuids = stemmed;
+            Some(RankedBucket {
+                uuids: exact,
+                score: 1.0,
+            })
+        } else if !stemmed.is_empty() {
+            candidates.uuids.clear();
+            Some(RankedBucket {
+                uuids: stemmed,
+                score: 0.0,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SearchResult {
+    pub items: HashMap<Uuid, Vec<SearchTermPositions>>,
+}
+
+#[derive(Debug)]
+pub struct SearchTermPositions {
+    pub matches: HashMap<String, Vec<usize>>, // <term, Vec<positions>
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResultTerm {
+    pub uuid: Uuid,
+    pub term: String,
+    pub positions: Vec<usize>,
+}
+
+/// A term from the index dictionary matched against a fuzzy query, along with how many
+/// edits it took to get there (0 for an exact match).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TolerantMatch {
+    pub term: String,
+    pub distance: usize,
+}
+
+/// The maximum edit distance tolerated for a query term of the given length, mirroring
+/// Meilisearch's word-length typo thresholds.
+pub fn max_typo_distance(query_len: usize) -> usize {
+    match query_len {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Computes the Levenshtein edit distance between `a` and `b` using a rolling single
+/// previous row, with early-abandon once the row minimum exceeds `max_distance` (in
+/// which case `None` is returned rather than the true distance).
+pub fn edit_distance(a: &str, b: &str, max_distance: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // quick reject: length difference alone exceeds the budget
+    if a.len().abs_diff(b.len()) > max_distance {
+        return None;
+    }
+
+    let mut prev_row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut cur_row = vec![0usize; b.len() + 1];
+        cur_row[0] = i + 1;
+        let mut row_min = cur_row[0];
+
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            cur_row[j + 1] = (prev_row[j] + cost)
+                .min(prev_row[j + 1] + 1)
+                .min(cur_row[j] + 1);
+            row_min = row_min.min(cur_row[j + 1]);
+        }
+
+        if row_min > max_distance {
+            return None; // early-abandon: no cell in this row can still land within budget
+        }
+        prev_row = cur_row;
+    }
+
+    let distance = prev_row[b.len()];
+    if distance > max_distance {
+        None
+    } else {
+        Some(distance)
+    }
+}
+
+/// A Levenshtein automaton over a fixed query string. Unlike `edit_distance`, which
+/// recomputes a full distance matrix from scratch for every candidate term, this
+/// automaton's state is a set of (query position, errors spent) pairs that is advanced
+/// one candidate character at a time, so scanning many candidates against the same query
+/// reuses the same per-character transition logic rather than re-deriving it per pair.
+pub struct LevenshteinAutomaton {
+    query: Vec<char>,
+    max_distance: usize,
+}
+
+/// The set of (query position, errors spent) pairs reachable after consuming some prefix
+/// of a candidate term.
+pub type AutomatonState = HashSet<(usize, usize)>;
+
+impl LevenshteinAutomaton {
+    pub fn new(query: &str, max_distance: usize) -> Self {
+        LevenshteinAutomaton {
+            query: query.chars().collect(),
+            max_distance,
+        }
+    }
+
+    /// The state before any candidate characters have been consumed: reachable by
+    /// deleting up to `max_distance` leading characters of the query outright.
+    pub fn start(&self) -> AutomatonState {
+        (0..=self.query.len().min(self.max_distance))
+            .map(|pos| (pos, pos))
+            .collect()
+    }
+
+    /// Advances `state` by one candidate character, returning the new state.
+    pub fn step(&self, state: &AutomatonState, c: char) -> AutomatonState {
+        let n = self.query.len();
+        let mut next: AutomatonState = HashSet::new();
+
+        for &(pos, errors) in state {
+            // match/substitution: consume both the query character (if any) and c
+            if pos < n {
+                if self.query[pos] == c {
+                    next.insert((pos + 1, errors));
+                } else if errors < self.max_distance {
+                    next.insert((pos + 1, errors + 1));
+                }
+            }
+            // insertion: c has no counterpart in the query
+            if errors < self.max_distance {
+                next.insert((pos, errors + 1));
+            }
+        }
+
+        // epsilon-closure over deletions: query characters skipped without consuming c
+        let mut frontier: Vec<(usize, usize)> = next.iter().copied().collect();
+        while let Some((pos, errors)) = frontier.pop() {
+            if pos < n && errors < self.max_distance {
+                let reached = (pos + 1, errors + 1);
+                if next.insert(reached) {
+                    frontier.push(reached);
+                }
+            }
+        }
+
+        next
+    }
+
+    /// Returns true if `state` represents a candidate that has fully matched the query
+    /// within the allowed number of edits.
+    pub fn is_match(&self, state: &AutomatonState) -> bool {
+        state
+            .iter()
+            .any(|&(pos, errors)| pos == self.query.len() && errors <= self.max_distance)
+    }
+}
+
+/// Finds every indexed term within `max_distance` edits of `term` by advancing a
+/// Levenshtein automaton over each candidate in the index dictionary, returning one
+/// `WordIndex` per matched indexed term (sorted by term), with that term's counts summed
+/// and its position lists unioned across every document's row. Independent of any
+/// one `Snip`, so higher layers (e.g. the boolean query evaluator) can OR the returned
+/// terms' documents together.
+pub fn fuzzy_term_matches(
+    conn: &Connection,
+    term: &str,
+    max_distance: usize,
+) -> Result<Vec<WordIndex>, Box<dyn Error>> {
+    let automaton = LevenshteinAutomaton::new(term, max_distance);
+
+    let mut stmt = conn.prepare("SELECT term, count, positions FROM snip_index_rs")?;
+    let rows = stmt.query_and_then([], |row| -> Result<(String, u64, String), rusqlite::Error> {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    })?;
+
+    let mut by_term: HashMap<String, (u64, Vec<u64>)> = HashMap::new();
+    for row in rows {
+        let (candidate, count, positions_str) = row?;
+
+        let mut state = automaton.start();
+        for c in candidate.chars() {
+            state = automaton.step(&state, c);
+        }
+        if !automaton.is_match(&state) {
+            continue;
+        }
+
+        let positions = WordIndex::positions_to_u64(positions_str)?;
+        let entry = by_term.entry(candidate).or_insert((0, Vec::new()));
+        entry.0 += count;
+        entry.1.extend(positions);
+    }
+
+    let mut matches: Vec<WordIndex> = by_term
+        .into_iter()
+        .map(|(term, (count, positions))| WordIndex {
+            term,
+            count,
+            positions,
+        })
+        .collect();
+    matches.sort_by(|a, b| a.term.cmp(&b.term));
+    Ok(matches)
+}
+
+/// Finds every indexed term whose *prefix* matches `term` within `max_distance` edits —
+/// i.e. some prefix of the candidate need only come within budget of all of `term`, not
+/// the whole candidate — by checking the automaton's match state after each character
+/// consumed rather than only once the candidate is exhausted. Powers the `search`
+/// subcommand's `--prefix` flag, so a still-being-typed final query term like "asyn"
+/// completes to indexed terms such as "async" or "asynchronous". `max_distance` of 0
+/// degrades to a literal (if scan-based rather than index-based) prefix match.
+pub fn fuzzy_prefix_matches(
+    conn: &Connection,
+    term: &str,
+    max_distance: usize,
+) -> Result<Vec<WordIndex>, Box<dyn Error>> {
+    let automaton = LevenshteinAutomaton::new(term, max_distance);
+
+    let mut stmt = conn.prepare("SELECT term, count, positions FROM snip_index_rs")?;
+    let rows = stmt.query_and_then([], |row| -> Result<(String, u64, String), rusqlite::Error> {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    })?;
+
+    let mut by_term: HashMap<String, (u64, Vec<u64>)> = HashMap::new();
+    for row in rows {
+        let (candidate, count, positions_str) = row?;
+
+        let mut state = automaton.start();
+        let mut matched = automaton.is_match(&state);
+        for c in candidate.chars() {
+            if matched {
+                break;
+            }
+            state = automaton.step(&state, c);
+            matched = automaton.is_match(&state);
+        }
+        if !matched {
+            continue;
+        }
+
+        let positions = WordIndex::positions_to_u64(positions_str)?;
+        let entry = by_term.entry(candidate).or_insert((0, Vec::new()));
+        entry.0 += count;
+        entry.1.extend(positions);
+    }
+
+    let mut matches: Vec<WordIndex> = by_term
+        .into_iter()
+        .map(|(term, (count, positions))| WordIndex {
+            term,
+            count,
+            positions,
+        })
+        .collect();
+    matches.sort_by(|a, b| a.term.cmp(&b.term));
+    Ok(matches)
+}
+
+/// Stems `term` the same way the index does, then returns every indexed `WordIndex` whose
+/// term is within `max_typo` edits of that stem via `fuzzy_term_matches`'s Levenshtein
+/// automaton walk, so ranked search can union in documents indexed under a typo'd variant
+/// of the query term. Running the automaton on the stem rather than the raw word avoids
+/// the automaton separately "discovering" every inflection of a word that stemming would
+/// already have collapsed to one term. Callers typically pick `max_typo` from the
+/// stemmed length with `max_typo_distance`, the same adaptive budget `search_index_fuzzy`
+/// uses.
+pub fn get_word_derivations(
+    conn: &Connection,
+    term: &str,
+    max_typo: usize,
+) -> Result<Vec<WordIndex>, Box<dyn Error>> {
+    let stem = stem_term(term);
+    fuzzy_term_matches(conn, &stem, max_typo)
+}
+
+/// Finds documents matching `term` within `max_distance` edits using a prefix-filtered
+/// variant of `fuzzy_term_matches`: rather than running the Levenshtein automaton over
+/// every distinct term in the index, this first narrows the candidate set with
+/// `prefix_term_matches` to terms sharing `term`'s first `len - max_distance` characters
+/// (so a term whose edits fall inside that prefix, such as a changed first letter, is not
+/// found), then discards any
+/// candidate whose length differs from `term`'s by more than `max_distance` before
+/// running the real edit-distance check on what's left. Returns each matching document
+/// paired with the smallest distance any of its matching terms achieved, closest first.
+pub fn search_fuzzy(
+    conn: &Connection,
+    term: &str,
+    max_distance: usize,
+) -> Result<Vec<(Uuid, usize)>, Box<dyn Error>> {
+    let term_chars: Vec<char> = term.chars().collect();
+    let prefix_len = term_chars.len().saturating_sub(max_distance);
+    let prefix: String = term_chars[..prefix_len].iter().collect();
+
+    let candidates = prefix_term_matches(conn, &prefix)?;
+
+    let mut best: HashMap<Uuid, usize> = HashMap::new();
+    for candidate in candidates {
+        if candidate.term.chars().count().abs_diff(term_chars.len()) > max_distance {
+            continue;
+        }
+        let distance = match edit_distance(term, &candidate.term, max_distance) {
+            Some(d) => d,
+            None => continue,
+        };
+        for uuid in search_uuids_matching_term(conn, &candidate.term)? {
+            best.entry(uuid)
+                .and_modify(|d| *d = (*d).min(distance))
+                .or_insert(distance);
+        }
+    }
+
+    let mut results: Vec<(Uuid, usize)> = best.into_iter().collect();
+    results.sort_by_key(|(_, distance)| *distance);
+    Ok(results)
+}
+
+/// Returns the byte-slice prefix of `s` covering its first `n` characters, or the whole
+/// string if it has fewer than `n`.
+fn truncate_chars(s: &str, n: usize) -> &str {
+    match s.char_indices().nth(n) {
+        Some((idx, _)) => &s[..idx],
+        None => s,
+    }
+}
+
+/// Core fuzzy-term lookup shared by every typo-tolerant entry point that only needs
+/// matching terms and their distances (as opposed to `fuzzy_term_matches`/
+/// `fuzzy_prefix_matches`, which also aggregate each match's postings): scans the distinct
+/// indexed terms once and returns those within `max_distance` edits of `term`, closest
+/// first. If `is_prefix`, `term` is treated as a partial (still-being-typed) word: each
+/// candidate is first truncated to `term`'s own length before the edit-distance check, so
+/// a longer indexed word counts as a match whenever its *prefix* is within budget rather
+/// than requiring the whole word to be close.
+fn fuzzy_term_candidates(
+    conn: &Connection,
+    term: &str,
+    max_distance: usize,
+    is_prefix: bool,
+) -> Result<Vec<(String, usize)>, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT term FROM snip_index_rs")?;
+    let candidates = stmt.query_and_then([], |row| row.get::<_, String>(0))?;
+
+    let term_len = term.chars().count();
+    let mut matches = Vec::new();
+    for candidate in candidates.flatten() {
+        let compared = if is_prefix {
+            truncate_chars(&candidate, term_len)
+        } else {
+            candidate.as_str()
+        };
+        if let Some(distance) = edit_distance(term, compared, max_distance) {
+            matches.push((candidate.clone(), distance));
+        }
+    }
+    matches.sort_by_key(|(_, distance)| *distance);
+    Ok(matches)
+}
+
+/// Gathers every distinct indexed term within `max_typo` edits of `term` via
+/// `fuzzy_term_candidates`. See that function for the meaning of `is_prefix`.
+fn derive_words(
+    conn: &Connection,
+    term: &str,
+    is_prefix: bool,
+    max_typo: usize,
+) -> Result<Vec<(String, u8)>, Box<dyn Error>> {
+    Ok(fuzzy_term_candidates(conn, term, max_typo, is_prefix)?
+        .into_iter()
+        .map(|(term, distance)| (term, distance as u8))
+        .collect())
+}
+
+/// Memoizes the indexed-term derivations of a fuzzy/prefix lookup for the lifetime of a
+/// single query, keyed by `(term, is_prefix, max_typo)`. `derive_words` rescans the whole
+/// term dictionary on every call; a query that asks for the same word's derivations more
+/// than once (e.g. once while resolving candidates, again while scoring them) would
+/// otherwise redo that scan, the same duplicated-work problem `QueryContext` solves for
+/// term postings.
+pub struct WordDerivationsCache<'conn> {
+    conn: &'conn Connection,
+    cache: RefCell<HashMap<(String, bool, u8), Vec<(String, u8)>>>,
+}
+
+impl<'conn> WordDerivationsCache<'conn> {
+    pub fn new(conn: &'conn Connection) -> Self {
+        WordDerivationsCache {
+            conn,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Applies the adaptive typo budget (0 edits for len<=4, 1 for len<=8, 2 longer) via
+    /// `TypoPolicy::LengthScaled`, then returns `term`'s memoized derivations under it.
+    pub fn derivations(&self, term: &str, is_prefix: bool) -> Result<Vec<(String, u8)>, Box<dyn Error>> {
+        let max_typo = TypoPolicy::LengthScaled.max_distance(term.chars().count()) as u8;
+        let key = (term.to_string(), is_prefix, max_typo);
+
+        if let Some(cached) = self.cache.borrow().get(&key) {
+            return Ok(cached.clone());
+        }
+
+        let derived = derive_words(self.conn, term, is_prefix, max_typo as usize)?;
+        self.cache.borrow_mut().insert(key, derived.clone());
+        Ok(derived)
+    }
+}
+
+/// Returns the terms of `snip_index_rs` within the allowed edit distance of `query_stem`
+/// (bounded by `max_typo_distance`) via `fuzzy_term_candidates`, closest matches first and
+/// exact matches (distance 0) ahead of fuzzy ones at the same distance.
+pub fn tolerant_terms(conn: &Connection, query_stem: &str) -> Result<Vec<TolerantMatch>, Box<dyn Error>> {
+    let max_distance = max_typo_distance(query_stem.chars().count());
+    Ok(fuzzy_term_candidates(conn, query_stem, max_distance, false)?
+        .into_iter()
+        .map(|(term, distance)| TolerantMatch { term, distance })
+        .collect())
+}
+
+/// Returns the union of document UUIDs matching `query_stem` or any indexed term within
+/// its typo budget, with exact matches resolved first. Term postings are routed through
+/// a throwaway `QueryContext`, since a typo-tolerant match commonly pulls in several
+/// indexed terms whose postings are cheaper to read from SQLite once each.
+pub fn search_data_tolerant(conn: &Connection, query_stem: &str) -> Result<Vec<Uuid>, Box<dyn Error>> {
+    let ctx = QueryContext::new(conn);
+    let matches = tolerant_terms(conn, query_stem)?;
+
+    let mut seen: HashSet<Uuid> = HashSet::new();
+    let mut results: Vec<Uuid> = Vec::new();
+    for m in matches {
+        for id in ctx.term_postings(&m.term)? {
+            if seen.insert(id) {
+                results.push(id);
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// Returns all indexed terms beginning with `prefix`, for offering completions or
+/// evaluating the still-being-typed word of a query as a prefix match.
+pub fn prefix_terms(conn: &Connection, prefix: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let pattern = format!("{}%", prefix);
+    let mut stmt = conn.prepare("SELECT DISTINCT term FROM snip_index_rs WHERE term LIKE :prefix")?;
+    let rows = stmt.query_and_then(
+        &[(":prefix", &pattern)],
+        |row| -> Result<String, Box<dyn Error>> { Ok(row.get(0)?) },
+    )?;
+
+    let mut terms = Vec::new();
+    for term in rows.flatten() {
+        terms.push(term);
+    }
+    Ok(terms)
+}
+
+/// Returns the lexicographically smallest string greater than every string that starts
+/// with `prefix`, by incrementing the last character. `term >= prefix AND term <
+/// prefix_upper_bound(prefix)` is equivalent to `term LIKE 'prefix%'` but, unlike `LIKE`,
+/// is a plain range comparison SQLite can satisfy directly from an index on `term` rather
+/// than a table scan — the technique `prefix_term_matches` uses for as-you-type lookups
+/// against `snip_index_rs_term_idx`. Returns `None` if `prefix` is empty or its last
+/// character is already the maximum `char` value.
+fn prefix_upper_bound(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    let last = chars.pop()?;
+    let incremented = char::from_u32(last as u32 + 1)?;
+    chars.push(incremented);
+    Some(chars.into_iter().collect())
+}
+
+/// Returns indexed terms beginning with `prefix`, one `WordIndex` per distinct term with
+/// its counts and positions aggregated across every document, suitable for driving
+/// incremental as-you-type search. Rather than `prefix_terms`'s `LIKE 'prefix%'`, this
+/// issues a plain `term >= :prefix AND term < :upper` range comparison, letting SQLite
+/// satisfy it directly from `snip_index_rs_term_idx` instead of scanning every row.
+pub fn prefix_term_matches(conn: &Connection, prefix: &str) -> Result<Vec<WordIndex>, Box<dyn Error>> {
+    let rows: Vec<(String, u64, String)> = match prefix_upper_bound(prefix) {
+        Some(upper) => {
+            let mut stmt = conn.prepare(
+                "SELECT term, count, positions FROM snip_index_rs \
+                 WHERE term >= :prefix AND term < :upper",
+            )?;
+            let rows = stmt.query_and_then(
+                rusqlite::named_params! { ":prefix": prefix, ":upper": upper },
+                |row| -> Result<(String, u64, String), rusqlite::Error> {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                },
+            )?;
+            rows.collect::<Result<_, _>>()?
+        }
+        None => {
+            let mut stmt = conn
+                .prepare("SELECT term, count, positions FROM snip_index_rs WHERE term >= :prefix")?;
+            let rows = stmt.query_and_then(
+                rusqlite::named_params! { ":prefix": prefix },
+                |row| -> Result<(String, u64, String), rusqlite::Error> {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                },
+            )?;
+            rows.collect::<Result<_, _>>()?
+        }
+    };
+
+    let mut by_term: HashMap<String, (u64, Vec<u64>)> = HashMap::new();
+    for (term, count, positions_str) in rows {
+        if !term.starts_with(prefix) {
+            continue;
+        }
+        let positions = WordIndex::positions_to_u64(positions_str)?;
+        let entry = by_term.entry(term).or_insert((0, Vec::new()));
+        entry.0 += count;
+        entry.1.extend(positions);
+    }
+
+    let mut matches: Vec<WordIndex> = by_term
+        .into_iter()
+        .map(|(term, (count, positions))| WordIndex { term, count, positions })
+        .collect();
+    matches.sort_by(|a, b| a.term.cmp(&b.term));
+    Ok(matches)
+}
+
+/// Returns the union of document uuids for every indexed term beginning with `prefix`.
+pub fn prefix_match(conn: &Connection, prefix: &str) -> Result<Vec<Uuid>, Box<dyn Error>> {
+    let mut seen: HashSet<Uuid> = HashSet::new();
+    let mut results: Vec<Uuid> = Vec::new();
+    for term in prefix_terms(conn, prefix)? {
+        for id in search_uuids_matching_term(conn, &term)? {
+            if seen.insert(id) {
+                results.push(id);
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// Type-ahead/autocomplete search: expands `prefix` into every indexed term beginning
+/// with it (via `prefix_terms`'s `SELECT DISTINCT term ... LIKE` scan), then folds each
+/// matched term's per-document positions into that document's `SearchQueryItem::matches`,
+/// keyed by the concrete matched term rather than `prefix` itself — so a caller can tell
+/// which completions of "lor" (e.g. "lorem", "lore") actually matched.
+///
+/// A thin wrapper over `search_prefix_cached` building a throwaway `SearchContext`; a
+/// caller issuing several lookups (e.g. completions re-typed on every keystroke) should
+/// build one `SearchContext` and call `search_prefix_cached` directly instead.
+pub fn search_prefix(conn: &Connection, prefix: &str) -> Result<SearchQueryResult, Box<dyn Error>> {
+    let ctx = SearchContext::new(conn);
+    search_prefix_cached(&ctx, prefix)
+}
+
+/// Same as `search_prefix`, but runs against a caller-supplied `SearchContext` so its
+/// term-postings and position lookups are shared with whatever else has run (or will
+/// run) on that context.
+pub fn search_prefix_cached(ctx: &SearchContext, prefix: &str) -> Result<SearchQueryResult, Box<dyn Error>> {
+    let mut by_uuid: HashMap<Uuid, HashMap<String, Vec<usize>>> = HashMap::new();
+
+    for term in prefix_terms(ctx.conn, prefix)? {
+        for uuid in ctx.cached_uuids_matching_term(&term)? {
+            let positions = ctx.cached_term_positions(&uuid, &term)?;
+            if !positions.is_empty() {
+                by_uuid.entry(uuid).or_default().insert(term.clone(), positions);
+            }
+        }
+    }
+
+    let mut items: Vec<SearchQueryItem> = by_uuid
+        .into_iter()
+        .map(|(uuid, matches)| SearchQueryItem { uuid, score: None, matches })
+        .collect();
+    items.sort_by_key(|item| item.uuid);
+
+    let mut query_result = SearchQueryResult { items };
+    score_search_query(ctx.conn, &mut query_result)?;
+    Ok(query_result)
+}
+
+/// Returns ids of documents that match the given term
+pub fn search_data(conn: &Connection, term: &String) -> Result<Vec<Uuid>, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT uuid FROM snip WHERE data LIKE :term")?;
+    let term_fuzzy = format!("{} {}{}", "%", term, "%");
+
+    let query_iter = stmt.query_map(&[(":term", &term_fuzzy)], |row| {
+        let id_str: String = row.get(0)?;
+        Ok(id_str)
+    })?;
+
+    let mut results: Vec<Uuid> = Vec::new();
+    for i in query_iter {
+        let id_str = match i {
+            Ok(v) => v,
+            Err(e) => return Err(Box::new(e)),
+        };
+        match Uuid::parse_str(&id_str) {
+            Ok(v) => results.push(v),
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+    // println!("results: {:?}", results);
+    Ok(results)
+}
+
+fn get_term_positions(
+    conn: &Connection,
+    id: &Uuid,
+    term: &String,
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    // a term can have a separate row per field (e.g. "body" and "name"); gather
+    // positions across all of them rather than just the first row returned
+    let mut stmt =
+        conn.prepare("SELECT positions FROM snip_index_rs WHERE uuid = :uuid AND term = :term")?;
+    let query_iter = stmt.query_map(&[(":uuid", &id.to_string()), (":term", term)], |row| {
+        let positions = row.get::<_, String>(0)?;
+        Ok(positions)
+    })?;
+
+    let mut positions: Vec<usize> = Vec::new();
+    for positions_str in query_iter.flatten() {
+        positions.extend(
+            positions_str
+                .split(',')
+                .map(|x| x.parse::<usize>().expect("converting db pos to usize")),
+        );
+    }
+    Ok(positions)
+}
+
+/// Search the index and return uuids that contain term. `DISTINCT`, since a term now
+/// gets a separate row per field it's indexed under (e.g. "body" and "name"), and a
+/// document matching in both fields should still surface as one result.
+pub fn search_uuids_matching_term(
+    conn: &Connection,
+    term: &String,
+) -> Result<Vec<Uuid>, Box<dyn Error>> {
+    let mut ids: Vec<Uuid> = Vec::new();
+    let mut stmt = conn.prepare("SELECT DISTINCT uuid FROM snip_index_rs WHERE term = :term")?;
+    let rows = stmt.query_and_then(
+        &[(":term", &term)],
+        |row| -> Result<String, Box<dyn Error>> {
+            let id: String = row.get(0)?;
+            Ok(id)
+        },
+    )?;
+
+    for row in rows.flatten() {
+        let id = Uuid::try_parse(row.as_str())?;
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+/// Expands `prefix` into every indexed term beginning with it, paired with a document
+/// known to contain that term — candidates for completing a trailing partial query word
+/// (e.g. `lor` -> `("lorem", uuid)`) before the boolean/proximity evaluation stages run.
+/// Prefers `snip_prefix_index_rs` (a direct equality lookup on documents already known to
+/// match `prefix`) and falls back to a `LIKE` scan of `snip_index_rs` when the prefix
+/// isn't present there, either because it was pruned by `prune_rare_prefixes` for being
+/// too rare or because the database predates the prefix index.
+pub fn get_by_prefix(conn: &Connection, prefix: &str) -> Result<Vec<(Uuid, String)>, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT 1 FROM snip_prefix_index_rs WHERE prefix = :prefix LIMIT 1")?;
+    let is_indexed: bool = stmt
+        .query_row(&[(":prefix", &prefix)], |row| row.get::<_, i64>(0))
+        .optional()?
+        .is_some();
+
+    if !is_indexed {
+        return prefix_terms_by_scan(conn, prefix);
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT uuid FROM snip_prefix_index_rs WHERE prefix = :prefix",
+    )?;
+    let uuids: HashSet<String> = stmt
+        .query_and_then(&[(":prefix", &prefix)], |row| row.get::<_, String>(0))?
+        .flatten()
+        .collect();
+
+    let mut results = Vec::new();
+    for (uuid, term) in prefix_terms_by_scan(conn, prefix)? {
+        if uuids.contains(&uuid.to_string()) {
+            results.push((uuid, term));
+        }
+    }
+    Ok(results)
+}
+
+fn prefix_terms_by_scan(conn: &Connection, prefix: &str) -> Result<Vec<(Uuid, String)>, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT uuid, term FROM snip_index_rs WHERE term LIKE :pattern")?;
+    let pattern = format!("{}%", prefix);
+    let rows = stmt.query_and_then(&[(":pattern", &pattern)], |row| {
+        Ok::<(String, String), rusqlite::Error>((row.get(0)?, row.get(1)?))
+    })?;
+
+    let mut results = Vec::new();
+    for row in rows.flatten() {
+        results.push((Uuid::try_parse(&row.0)?, row.1));
+    }
+    Ok(results)
+}
+
+pub fn search_all_present(
+    conn: &Connection,
+    terms: Vec<String>,
+) -> Result<SearchResult, Box<dyn Error>> {
+    let mut result = SearchResult {
+        items: HashMap::new(),
+    };
+
+    let mut result_prelim: Vec<SearchResultTerm> = Vec::new();
+
+    for term in terms {
+        let mut stmt =
+            conn.prepare("SELECT uuid, positions FROM snip_index_rs WHERE term = :term")?;
+        let query_iter = stmt.query_map(&[(":term", &term)], |row| {
+            let id = row.get::<_, String>(0)?;
+            let pos_str = row.get::<_, String>(1)?;
+            Ok((id, pos_str))
+        })?;
+        for id_str in query_iter.flatten() {
+            let uuid = Uuid::try_parse(id_str.0.as_str())?;
+            let positions: Vec<usize> = id_str
+                .1
+                .split(',')
+                .map(|x| {
+                    x.parse::<usize>()
+                        .expect("parsing positions from db string")
+                })
+                .collect();
+            result_prelim.push(SearchResultTerm {
+                uuid,
+                term: term.clone(),
+                positions,
+            });
+        }
+    }
+
+    // add all matches to result hashmap
+    for rt in result_prelim {
+        let mut item = SearchTermPositions {
+            matches: HashMap::new(),
+        };
+        item.matches.insert(rt.term, rt.positions);
+
+        // add to final results
+        if result.items.get(&rt.uuid).is_none() {
+            result.items.insert(rt.uuid, Vec::new());
+        }
+        result.items.get_mut(&rt.uuid).unwrap().push(item); // FIXME - no unwrap
+    }
+    Ok(result)
+}
+
+/// Returns every snip uuid containing the supplied partial string, in no particular
+/// order. Used both to resolve unambiguous partials and to give a caller the full
+/// candidate list when `search_uuid` reports `SnipError::Ambiguous`.
+pub fn search_uuid_all(conn: &Connection, id_partial: &str) -> Result<Vec<Uuid>, SnipError> {
+    let mut stmt = match conn.prepare("SELECT uuid from snip WHERE uuid LIKE :id") {
+        Ok(v) => v,
+        Err(e) => return Err(SnipError::General(format!("{}", e))),
+    };
+    let id_partial_fuzzy = format!("{}{}{}", "%", id_partial, "%");
+
+    let rows = match stmt.query_map(&[(":id", &id_partial_fuzzy)], |row| {
+        let id_str: String = row.get(0)?;
+        Ok(id_str)
+    }) {
+        Ok(v) => v,
+        Err(e) => return Err(SnipError::General(format!("{}", e))),
+    };
+
+    let mut ids = Vec::new();
+    for row in rows {
+        let id_str = row.map_err(|e| SnipError::General(format!("{}", e)))?;
+        ids.push(Uuid::parse_str(&id_str).map_err(|e| SnipError::General(format!("{}", e)))?);
+    }
+    Ok(ids)
+}
+
+/// Search for a uuid matching the supplied partial string.
+/// The partial uuid must match a unique record to return the result; a fragment matching
+/// more than one snip returns `SnipError::Ambiguous` carrying every matching uuid so the
+/// caller (e.g. a CLI) can prompt the user to disambiguate rather than guessing.
+pub fn search_uuid(conn: &Connection, id_partial: &str) -> Result<Uuid, SnipError> {
+    let ids = search_uuid_all(conn, id_partial)?;
+
+    match ids.len() {
+        0 => Err(SnipError::UuidNotFound(format!(
+            "The document id was not found using id {}",
+            id_partial
+        ))),
+        1 => Ok(ids[0]),
+        _ => Err(SnipError::Ambiguous(ids)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snip;
+    use crate::snip::test_prep::*;
+    use std::collections::HashMap;
+    use std::error::Error;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_search_all_present() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let stemmer = rust_stemmers::Stemmer::create(rust_stemmers::Algorithm::English);
+
+        let terms: Vec<String> = vec![
+            "lorem".to_string(),
+            "ipsum".to_string(),
+            "dolor".to_string(),
+        ];
+        let stems: Vec<String> = terms.iter().map(|w| stemmer.stem(w).to_string()).collect();
+        let result = search_all_present(&conn, stems)?;
+
+        println!("number of results: {}", result.items.len());
+        println!("{:#?}", result);
+        /*
+        for (k, v) in result.items {
+            let s = snip::get_from_uuid(&conn, &k)?;
+            println!("{} {}", s.uuid, s.name);
+            println!("  {:#?}", v);
+        }
+         */
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_by_prefix_expands_to_completed_terms() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let matches = get_by_prefix(&conn, "lor")?;
+        assert!(matches.iter().any(|(_, term)| term == "lorem"));
+        for (_, term) in &matches {
+            assert!(term.starts_with("lor"));
+        }
+
+        // a prefix present nowhere in the corpus simply yields no candidates
+        assert!(get_by_prefix(&conn, "zzznonexistent")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_structured() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database()?;
+        snip::index_all_items(&conn)?;
+
+        let query = SearchQuery {
+            // terms_include: vec!["ipsum".to_string(), "dolor".to_string()],
+            terms_include: vec!["in".to_string(), "is".to_string()],
+            terms_exclude: vec!["fuzz".to_string()],
+            terms_optional: vec![],
+            method: SearchMethod::IndexStem,
+            uuids: vec![],
+            prefix_distance: None,
+        };
+
+        let expect = SearchQueryResult {
+            items: vec![SearchQueryItem {
+                uuid: Uuid::try_parse("412f7ca8-824c-4c70-80f0-4cca6371e45a")?,
+                score: None,
+                matches: HashMap::from([
+                    (
+                        "in".to_string(),
+                        vec![
+                            116, 159, 352, 730, 794, 809, 1043, 1114, 1143, 1317, 1341, 1362, 1397,
+                            1417,
+                        ],
+                    ),
+                    (
+                        "is".to_string(),
+                        vec![
+                            100, 110, 359, 591, 715, 806, 818, 938, 954, 1023, 1034, 1053, 1171,
+                            1218, 1266, 1370, 1377, 1387, 1393, 1414, 1439, 1512, 1517, 1542, 1591,
+                        ],
+                    ),
+                ]),
+            }],
+        };
+
+        let result = search_structured(&conn, query)?;
+        // println!("expect: {:?}", expect);
+        // println!("result: {:?}", result);
+
+        // verify id, length, and keys only
+        let expect_item = expect.items.first().expect("getting first expect_item");
+        let result_item = result.items.first().expect("getting first result_item");
+        if expect_item.uuid != result_item.uuid {
+            panic!(
+                "expected uuid {} got {}",
+                expect_item.uuid, result_item.uuid
+            );
+        }
+
+        if expect_item.matches != result_item.matches {
+            panic!("expected item {:?} got {:?}", expect_item, result_item);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_structured_uuids() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database()?;
+        snip::index_all_items(&conn)?;
+
+        // Lorem ipsum
+        let id: Uuid = Uuid::try_parse(ID_STR)?;
+        let query = SearchQuery {
+            terms_include: vec!["lorem".to_string(), "ipsum".to_string()],
+            terms_exclude: vec!["fuzz".to_string()],
+            terms_optional: vec![],
+            method: SearchMethod::IndexStem,
+            uuids: vec![id],
+            prefix_distance: None,
+        };
+        let result = search_structured(&conn, query)?;
+        // println!("result: {:#?}", result);
+        let item = result.items.get(0).unwrap();
+        // check length of positions for "lorem"
+        let item_lorem_len = item.matches.get("lorem").unwrap().len();
+        let item_lorem_len_expect = 2;
+        if item_lorem_len != item_lorem_len_expect {
+            panic!(
+                "expected {} matches for 'lorem', got {}",
+                item_lorem_len_expect, item_lorem_len
+            );
+        }
+        // check length of positions for "ipsum"
+        let item_ipsum_len = item.matches.get("ipsum").unwrap().len();
+        let item_ipsum_len_expect = 5;
+        if item_ipsum_len != item_ipsum_len_expect {
+            panic!(
+                "expected {} matches for 'ipsum', got {}",
+                item_ipsum_len_expect, item_ipsum_len
+            );
+        }
+
+        // Fuzzing document
+        let id = Uuid::try_parse("990a917e-66d3-404b-9502-e8341964730b")?;
+        let query = SearchQuery {
+            terms_include: vec!["fuzz".to_string(), "random".to_string()],
+            terms_exclude: vec!["lorem".to_string()],
+            terms_optional: vec![],
+            method: SearchMethod::IndexStem,
+            uuids: vec![id],
+            prefix_distance: None,
+        };
+        let result = search_structured(&conn, query)?;
+        // println!("result: {:#?}", result);
+        // check length of positions for "fuzz"
+        let item = result.items.get(0).unwrap();
+        let item_fuzz_len = item.matches.get("fuzz").unwrap().len();
+        let item_fuzz_len_expect = 7;
+        if item_fuzz_len != item_fuzz_len_expect {
+            panic!(
+                "expected {} matches for 'fuzz', got {}",
+                item_fuzz_len_expect, item_fuzz_len
+            );
+        }
+        // check length of positions for "random"
+        let item_random_len = item.matches.get("random").unwrap().len();
+        let item_random_len_expect = 1;
+        if item_random_len != item_random_len_expect {
+            panic!(
+                "expected {} matches for 'random', got {}",
+                item_random_len_expect, item_random_len
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_structured_fuzzy_tolerates_typo() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database()?;
+        snip::index_all_items(&conn)?;
+
+        let id = Uuid::try_parse(ID_STR)?;
+
+        // "lorm" is a one-edit typo of the indexed term "lorem"; an exact search finds
+        // nothing, but IndexFuzzy(1) should still match the document
+        let exact_query = SearchQuery {
+            terms_include: vec!["lorm".to_string()],
+            terms_exclude: vec![],
+            terms_optional: vec![],
+            method: SearchMethod::IndexStem,
+            uuids: vec![id],
+            prefix_distance: None,
+        };
+        let exact_result = search_structured(&conn, exact_query)?;
+        assert!(exact_result.items[0].matches.get("lorm").unwrap().is_empty());
+
+        let fuzzy_query = SearchQuery {
+            terms_include: vec!["lorm".to_string()],
+            terms_exclude: vec![],
+            terms_optional: vec![],
+            method: SearchMethod::IndexFuzzy(1),
+            uuids: vec![id],
+            prefix_distance: None,
+        };
+        let fuzzy_result = search_structured(&conn, fuzzy_query)?;
+        assert!(!fuzzy_result.items[0].matches.get("lorm").unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_structured_fuzzy_auto_derives_distance_from_term_length() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database()?;
+        snip::index_all_items(&conn)?;
+
+        let id = Uuid::try_parse(ID_STR)?;
+
+        // "lorm" is 4 characters, so max_typo_distance gives it a budget of 1 edit,
+        // which is exactly the distance to the indexed term "lorem" — no --typo value
+        // is given, IndexFuzzyAuto must pick it automatically
+        let auto_query = SearchQuery {
+            terms_include: vec!["lorm".to_string()],
+            terms_exclude: vec![],
+            terms_optional: vec![],
+            method: SearchMethod::IndexFuzzyAuto,
+            uuids: vec![id],
+            prefix_distance: None,
+        };
+        let auto_result = search_structured(&conn, auto_query)?;
+        assert!(!auto_result.items[0].matches.get("lorm").unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_structured_prefix_completes_last_term() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database()?;
+        snip::index_all_items(&conn)?;
+
+        let id = Uuid::try_parse(ID_STR)?;
+
+        // "lor" is not itself an indexed term, but is a prefix of the indexed term
+        // "lorem"; an exact search finds nothing, while prefix_distance = Some(0)
+        // should still match the document via its "lorem" occurrences
+        let exact_query = SearchQuery {
+            terms_include: vec!["lor".to_string()],
+            terms_exclude: vec![],
+            terms_optional: vec![],
+            method: SearchMethod::IndexStem,
+            uuids: vec![id],
+            prefix_distance: None,
+        };
+        let exact_result = search_structured(&conn, exact_query)?;
+        assert!(exact_result.items[0].matches.get("lor").unwrap().is_empty());
+
+        let prefix_query = SearchQuery {
+            terms_include: vec!["lor".to_string()],
+            terms_exclude: vec![],
+            terms_optional: vec![],
+            method: SearchMethod::IndexStem,
+            uuids: vec![id],
+            prefix_distance: Some(0),
+        };
+        let prefix_result = search_structured(&conn, prefix_query)?;
+        assert!(!prefix_result.items[0].matches.get("lor").unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_structured_index_prefix_needs_no_explicit_prefix_distance() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database()?;
+        snip::index_all_items(&conn)?;
+
+        let id = Uuid::try_parse(ID_STR)?;
+
+        // SearchMethod::IndexPrefix alone, with prefix_distance left at None, must match
+        // the same way IndexStem + prefix_distance: Some(0) does
+        let query = SearchQuery {
+            terms_include: vec!["lor".to_string()],
+            terms_exclude: vec![],
+            terms_optional: vec![],
+            method: SearchMethod::IndexPrefix,
+            uuids: vec![id],
+            prefix_distance: None,
+        };
+        let result = search_structured(&conn, query)?;
+        assert!(!result.items[0].matches.get("lor").unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_prefix_keys_matches_by_concrete_completed_term() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database()?;
+        snip::index_all_items(&conn)?;
+
+        let id = Uuid::try_parse(ID_STR)?;
+        let result = search_prefix(&conn, "lor")?;
+
+        let item = result
+            .items
+            .iter()
+            .find(|item| item.uuid == id)
+            .expect("lorem ipsum document completes \"lor\"");
+        // keyed by the concrete matched term ("lorem"), not the typed prefix ("lor")
+        assert!(item.matches.get("lor").is_none());
+        assert!(!item.matches.get("lorem").unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_structured_same_term_included_and_excluded_cancels_out() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database()?;
+        snip::index_all_items(&conn)?;
+
+        // "lorem" requested as both a mandatory and a disqualifying term exercises the
+        // bitmap candidate phase's per-invocation cache (the same posting bitmap is
+        // resolved once, then reused for both the AND and the ANDNOT), and the result
+        // must come out empty: nothing can both include and exclude the same term
+        let query = SearchQuery {
+            terms_include: vec!["lorem".to_string()],
+            terms_exclude: vec!["lorem".to_string()],
+            terms_optional: vec![],
+            method: SearchMethod::IndexStem,
+            uuids: vec![],
+            prefix_distance: None,
+        };
+        let result = search_structured(&conn, query)?;
+        assert!(result.items.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_structured_optional_terms_boost_without_filtering() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database()?;
+        snip::index_all_items(&conn)?;
+
+        // "ipsum" is only optional here, not mandatory, but the "lorem ipsum" document
+        // does contain it, so its positions must still surface in `matches`
+        let id = Uuid::try_parse(ID_STR)?;
+        let query = SearchQuery {
+            terms_include: vec!["lorem".to_string()],
+            terms_exclude: vec![],
+            terms_optional: vec!["ipsum".to_string()],
+            method: SearchMethod::IndexStem,
+            uuids: vec![id],
+            prefix_distance: None,
+        };
+        let result = search_structured(&conn, query)?;
+        let item = result.items.first().expect("lorem document matches");
+        assert_eq!(item.matches.get("lorem").unwrap().len(), 2);
+        assert_eq!(item.matches.get("ipsum").unwrap().len(), 5);
+
+        // an optional term a document does NOT contain must neither exclude the
+        // document nor appear (empty) in its matches
+        let query = SearchQuery {
+            terms_include: vec!["lorem".to_string()],
+            terms_exclude: vec![],
+            terms_optional: vec!["zzznonexistent".to_string()],
+            method: SearchMethod::IndexStem,
+            uuids: vec![id],
+            prefix_distance: None,
+        };
+        let result = search_structured(&conn, query)?;
+        let item = result.items.first().expect("optional term must not filter candidates");
+        assert!(item.matches.get("zzznonexistent").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_uuid() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+
+        let id = Uuid::try_parse(ID_STR)?;
+        let partials = fragment_uuid(id);
+
+        /*
+        println!("ba652e2d-b248-4bcc-b36e-c26c0d0e8002");
+        for p in &partials {
+            println!("{} {}", p.0, p.1);
+        }
+        */
+
+        let expect = match Uuid::parse_str(ID_STR) {
+            Ok(v) => v,
+            Err(e) => panic!("{}", e),
+        };
+
+        // test all uuid string partials
+        for p in &partials {
+            println!("search uuid string: {}", p.0);
+            let id = search_uuid(&conn, p.0);
+            match id {
+                Ok(v) => assert_eq!(expect, v),
+                Err(e) => panic!("{}, full: {}, partial: {}", e, ID_STR, &p.0),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_index_ranked() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let ranked = search_index_ranked(&conn, &["lorem", "ipsum"])?;
+        assert!(!ranked.is_empty());
+        for pair in ranked.windows(2) {
+            assert!(pair[0].1 >= pair[1].1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_ranked_matches_search_index_ranked() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let ranked = search_ranked(&conn, "lorem ipsum")?;
+        let expected = search_index_ranked(&conn, &["lorem", "ipsum"])?;
+        assert_eq!(ranked, expected);
+        assert!(!ranked.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_phrase_adjacent_and_near() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        // an adjacent phrase match (max_gap == 1) is a subset of a looser near-match
+        let adjacent = search_phrase(&conn, &["lorem", "ipsum"], 1)?;
+        let near = search_phrase(&conn, &["lorem", "ipsum"], 5)?;
+        for (uuid, _) in &adjacent {
+            assert!(near.iter().any(|(n, _)| n == uuid));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_phrase_str_finds_tightest_span() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let matches = search_phrase_str(&conn, "lorem ipsum", 0)?;
+        assert!(!matches.is_empty());
+        for (_, _, span) in &matches {
+            // adjacent (slop=0) terms always span exactly one position apart
+            assert_eq!(*span, 1);
+        }
+
+        // a uuid matching at slop=0 must also match at a looser slop
+        let loose = search_phrase_str(&conn, "lorem ipsum", 3)?;
+        for (uuid, ..) in &matches {
+            assert!(loose.iter().any(|(n, ..)| n == uuid));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_index_fuzzy_groups_by_distance() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        // "lorex" has 5 characters, so LengthScaled allows it 1 edit, enough to reach "lorem"
+        let grouped = search_index_fuzzy(&conn, "lorex", TypoPolicy::LengthScaled)?;
+        assert!(grouped
+            .iter()
+            .any(|(distance, ids)| *distance >= 1 && !ids.is_empty()));
+        // groups must come back sorted by ascending distance
+        for pair in grouped.windows(2) {
+            assert!(pair[0].0 <= pair[1].0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_complete_term() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let completions = complete_term(&conn, "LOR", 5)?;
+        assert!(completions.iter().any(|(term, _)| term == "lorem"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_uuid_ambiguous() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+
+        // insert a second snip whose uuid shares a fragment with ID_STR, so a query for
+        // that fragment becomes ambiguous
+        let other_id = "ba652e2d-0000-0000-0000-000000000000";
+        conn.execute(
+            "INSERT INTO snip (uuid, timestamp, name, data) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![other_id, "2024-01-01T00:00:00+00:00", "other", "other data"],
+        )?;
+
+        let shared_fragment = "ba652e2d";
+        match search_uuid(&conn, shared_fragment) {
+            Err(SnipError::Ambiguous(ids)) => assert_eq!(ids.len(), 2),
+            other => panic!("expected SnipError::Ambiguous, got {:?}", other),
+        }
+
+        let all = search_uuid_all(&conn, shared_fragment).expect("collecting all candidates");
+        assert_eq!(all.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("lorem", "lorem", 2), Some(0));
+        assert_eq!(edit_distance("lorem", "lorm", 2), Some(1));
+        assert_eq!(edit_distance("lorem", "lorems", 2), Some(1));
+        assert_eq!(edit_distance("lorem", "dolor", 2), None);
+        assert_eq!(edit_distance("cat", "hat", 1), Some(1));
+    }
+
+    #[test]
+    fn test_max_typo_distance() {
+        assert_eq!(max_typo_distance(3), 0);
+        assert_eq!(max_typo_distance(7), 1);
+        assert_eq!(max_typo_distance(8), 2);
+    }
+
+    #[test]
+    fn test_levenshtein_automaton_matches() {
+        let automaton = LevenshteinAutomaton::new("lorem", 1);
+
+        let accepts = |candidate: &str| {
+            let mut state = automaton.start();
+            for c in candidate.chars() {
+                state = automaton.step(&state, c);
+            }
+            automaton.is_match(&state)
+        };
+
+        assert!(accepts("lorem"));
+        assert!(accepts("lorm")); // deletion
+        assert!(accepts("loreem")); // insertion
+        assert!(accepts("lorex")); // substitution
+        assert!(!accepts("dolor"));
+    }
+
+    #[test]
+    fn test_parse_query_stems_leaf_terms() {
+        // "running" should resolve to the same stem `index()` would store for it
+        assert_eq!(
+            parse_query("running dogs"),
+            Operation::And(vec![
+                Operation::Query("run".to_string()),
+                Operation::Prefix("dogs".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_query_drops_stop_words_from_multi_leaf_groups() {
+        // "the" is a stop word and is never written to snip_index_rs, so it's dropped
+        // from the group rather than requiring an impossible exact match
+        assert_eq!(
+            parse_query("the lorem ipsum"),
+            Operation::And(vec![
+                Operation::Query("lorem".to_string()),
+                Operation::Prefix("ipsum".to_string()),
+            ])
+        );
+
+        // a group made up entirely of stop words is left intact rather than emptied out;
+        // here "the that" is its own OR-branch, so neither word becomes the trailing
+        // Prefix leaf (that's "ipsum", in the other branch) and both stay as Query leaves
+        assert_eq!(
+            parse_query("the that OR lorem ipsum"),
+            Operation::Or(vec![
+                Operation::And(vec![
+                    Operation::Query("the".to_string()),
+                    Operation::Query("that".to_string()),
+                ]),
+                Operation::And(vec![
+                    Operation::Query("lorem".to_string()),
+                    Operation::Prefix("ipsum".to_string()),
+                ]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_search_query_parses_and_evaluates_in_one_call() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let combined = search_query(&conn, "lorem ipsum")?;
+        let separately = evaluate_operation(&conn, &parse_query("lorem ipsum"))?;
+        assert_eq!(combined, separately);
+        assert!(!combined.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_operation_from_search_query_matches_flat_candidate_set() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database()?;
+        snip::index_all_items(&conn)?;
+
+        let search_query_flat = SearchQuery {
+            terms_include: vec!["lorem".to_string(), "ipsum".to_string()],
+            terms_exclude: vec!["fuzz".to_string()],
+            terms_optional: vec![],
+            method: SearchMethod::IndexStem,
+            uuids: vec![],
+            prefix_distance: None,
+        };
+
+        let flat_result = search_structured(&conn, search_query_flat.clone())?;
+        let mut flat_uuids: Vec<Uuid> = flat_result.items.iter().map(|item| item.uuid).collect();
+        flat_uuids.sort_unstable();
+
+        let op = operation_from_search_query(&search_query_flat);
+        let mut tree_uuids: Vec<Uuid> = evaluate_operation(&conn, &op)?.into_iter().collect();
+        tree_uuids.sort_unstable();
+
+        assert_eq!(flat_uuids, tree_uuids);
+        assert!(!flat_uuids.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_boolean_query_resolves_parenthesized_or_and_not() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database()?;
+        snip::index_all_items(&conn)?;
+
+        // "(lorem OR ipsum) NOT fuzz" must match the lorem ipsum document (no "fuzz")
+        // and must not match the fuzzing document (contains "fuzz")
+        let result = search_boolean_query(&conn, "(lorem OR ipsum) NOT fuzz")?;
+        let id_lorem_ipsum = Uuid::try_parse(ID_STR)?;
+        let id_fuzz = Uuid::try_parse("990a917e-66d3-404b-9502-e8341964730b")?;
+
+        let uuids: Vec<Uuid> = result.items.iter().map(|item| item.uuid).collect();
+        assert!(uuids.contains(&id_lorem_ipsum));
+        assert!(!uuids.contains(&id_fuzz));
+
+        // matches is populated for whichever of the OR'd terms actually occurs, and
+        // never for the negated term
+        let item = result.items.iter().find(|item| item.uuid == id_lorem_ipsum).unwrap();
+        assert!(item.matches.contains_key("lorem") || item.matches.contains_key("ipsum"));
+        assert!(!item.matches.contains_key("fuzz"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_query_fuses_near_operator_into_single_leaf() {
+        assert_eq!(
+            parse_query("lorem NEAR/5 ipsum"),
+            Operation::Near("lorem".to_string(), "ipsum".to_string(), 5)
+        );
+    }
+
+    #[test]
+    fn test_evaluate_near_matches_terms_within_gap() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let op = parse_query("lorem NEAR/1000 ipsum");
+        let near_matches = evaluate_operation(&conn, &op)?;
+        assert!(!near_matches.is_empty());
+
+        // a near-zero gap should be far too tight for any real document's "lorem"/"ipsum"
+        // occurrences, which are not guaranteed to be adjacent
+        let op_tight = Operation::Near("lorem".to_string(), "ipsum".to_string(), 0);
+        let tight_matches = evaluate_operation(&conn, &op_tight)?;
+        assert!(tight_matches.len() <= near_matches.len());
+
+        for uuid in &near_matches {
+            assert!(near_anchor_positions(&conn, uuid, "lorem", "ipsum", 1000)?.is_some());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fuzzy_term_matches() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let matches = fuzzy_term_matches(&conn, "lorm", 1)?;
+        assert!(matches.iter().any(|w| w.term == "lorem"));
+
+        let exact_only = fuzzy_term_matches(&conn, "lorm", 0)?;
+        assert!(!exact_only.iter().any(|w| w.term == "lorem"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_word_derivations_stems_before_matching() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        // "lorems" stems to "lorem", so this should derive "lorem" itself (distance 0)
+        // plus anything else within the budget, rather than failing to match it at all
+        let max_typo = max_typo_distance(stem_term("lorems").chars().count());
+        let derivations = get_word_derivations(&conn, "lorems", max_typo)?;
+        assert!(derivations.iter().any(|w| w.term == "lorem"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_fuzzy_matches_prefix_filtered_candidates() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let fuzzy = search_fuzzy(&conn, "lorm", 1)?;
+        let exact = search_uuids_matching_term(&conn, &"lorem".to_string())?;
+        for uuid in &exact {
+            assert!(fuzzy.iter().any(|(u, _)| u == uuid));
+        }
+        assert!(!fuzzy.is_empty());
+
+        let exact_only = search_fuzzy(&conn, "lorm", 0)?;
+        assert!(exact_only.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_word_derivations_cache_memoizes_and_matches() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let cache = WordDerivationsCache::new(&conn);
+        // "lorex" has 5 characters, so the LengthScaled budget is 1 edit
+        let first = cache.derivations("lorex", false)?;
+        assert!(first.iter().any(|(term, distance)| term == "lorem" && *distance == 1));
+
+        // a second call for the same key returns the identical memoized result
+        let second = cache.derivations("lorex", false)?;
+        assert_eq!(first, second);
+
+        // is_prefix truncates candidates to "lor"'s own length before comparing, so an
+        // exact-prefix candidate like "lorem" matches at distance 0 instead of needing
+        // the whole word to be close
+        let prefix_derivations = cache.derivations("lor", true)?;
+        assert!(prefix_derivations
+            .iter()
+            .any(|(term, distance)| term == "lorem" && *distance == 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_pipeline_orders_words_then_proximity() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let query_terms = vec!["lorem".to_string(), "ipsum".to_string()];
+        let candidates = CandidateSet {
+            uuids: search_uuids_matching_term(&conn, &"lorem".to_string())?,
+            raw_terms: query_terms.clone(),
+            query_terms,
+        };
+        assert!(!candidates.uuids.is_empty());
+
+        let ranked = run_pipeline(
+            &candidates,
+            vec![
+                Box::new(WordsCriterion::new(&conn)),
+                Box::new(ProximityCriterion::new(&conn)),
+            ],
+        );
+
+        // every candidate survives the pipeline, each carrying a "words" then a
+        // "proximity" entry in its breakdown
+        let ranked_uuids: HashSet<Uuid> = ranked.iter().map(|r| r.uuid).collect();
+        let candidate_uuids: HashSet<Uuid> = candidates.uuids.iter().cloned().collect();
+        assert_eq!(ranked_uuids, candidate_uuids);
+        for scored in &ranked {
+            let names: Vec<&str> = scored.breakdown.iter().map(|(name, _)| name.as_str()).collect();
+            assert_eq!(names, vec!["words", "proximity"]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_query() {
+        // the last bare word resolves as a Prefix leaf; earlier words stay exact
+        assert_eq!(
+            parse_query("lorem ipsum"),
+            Operation::And(vec![
+                Operation::Query("lorem".to_string()),
+                Operation::Prefix("ipsum".to_string()),
+            ])
+        );
+        assert_eq!(
+            parse_query("lorem OR ipsum"),
+            Operation::Or(vec![
+                Operation::Query("lorem".to_string()),
+                Operation::Prefix("ipsum".to_string()),
+            ])
+        );
+        assert_eq!(
+            parse_query("lorem -ipsum"),
+            Operation::And(vec![
+                Operation::Prefix("lorem".to_string()),
+                Operation::Not(Box::new(Operation::Query("ipsum".to_string()))),
+            ])
+        );
+        assert_eq!(
+            parse_query("\"lorem ipsum\""),
+            Operation::Phrase(vec!["lorem".to_string(), "ipsum".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_parse_query_groups_parenthesized_alternatives() {
+        // without grouping "rust AND tokio OR async" would bind OR across the whole
+        // query; the parenthesized group keeps "tokio OR async" together as one leaf
+        // "rust" is the only bare word outside the group, so the last-bare-word-is-a-
+        // Prefix heuristic still applies to it, same as it would with no group present
+        assert_eq!(
+            parse_query("rust (tokio OR async)"),
+            Operation::And(vec![
+                Operation::Prefix("rust".to_string()),
+                Operation::Or(vec![
+                    Operation::Query("tokio".to_string()),
+                    Operation::Prefix("async".to_string()),
+                ]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_query_not_keyword_matches_leading_dash() {
+        assert_eq!(
+            parse_query("rust NOT windows"),
+            parse_query("rust -windows"),
+        );
+    }
+
+    #[test]
+    fn test_parse_query_not_negates_a_group() {
+        assert_eq!(
+            parse_query("rust NOT (windows OR macos)"),
+            Operation::And(vec![
+                Operation::Prefix("rust".to_string()),
+                Operation::Not(Box::new(Operation::Or(vec![
+                    Operation::Query("window".to_string()),
+                    Operation::Prefix("macos".to_string()),
+                ]))),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_prefix_upper_bound() {
+        assert_eq!(prefix_upper_bound("lor"), Some("los".to_string()));
+        assert_eq!(prefix_upper_bound(""), None);
+    }
+
+    #[test]
+    fn test_prefix_term_matches() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let matches = prefix_term_matches(&conn, "lor")?;
+        assert!(matches.iter().any(|w| w.term == "lorem"));
+
+        let matches_direct = prefix_terms(&conn, "lor")?;
+        let mut range_terms: Vec<String> = matches.iter().map(|w| w.term.clone()).collect();
+        range_terms.sort();
+        let mut direct_terms = matches_direct;
+        direct_terms.sort();
+        assert_eq!(range_terms, direct_terms);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefix_match() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let terms = prefix_terms(&conn, "lor")?;
+        assert!(terms.iter().any(|t| t == "lorem"));
+
+        let exact = search_uuids_matching_term(&conn, &"lorem".to_string())?;
+        let prefixed = prefix_match(&conn, "lor")?;
+        for uuid in exact {
+            assert!(prefixed.contains(&uuid));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate_operation() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let and_result = evaluate_operation(&conn, &parse_query("lorem ipsum"))?;
+        let or_result = evaluate_operation(&conn, &parse_query("lorem OR ipsum"))?;
+        // every document matching both terms also matches either term
+        assert!(and_result.is_subset(&or_result));
+
+        let phrase_result = evaluate_operation(&conn, &parse_query("\"lorem ipsum\""))?;
+        // an adjacent phrase match is necessarily also an AND match on its words
+        assert!(phrase_result.is_subset(&and_result));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate_operation_grouped_query_matches_ungrouped_or() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        // a parenthesized group is just a single leaf, so wrapping an OR in parens must
+        // evaluate identically to the bare (unparenthesized) OR
+        let grouped = evaluate_operation(&conn, &parse_query("(lorem OR ipsum)"))?;
+        let bare = evaluate_operation(&conn, &parse_query("lorem OR ipsum"))?;
+        assert_eq!(grouped, bare);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_term_span() {
+        // "a" at 0 and 10, "b" at 5 and 6: tightest window holding both is [6, 10], span 4
+        let positions = vec![vec![0, 10], vec![5, 6]];
+        assert_eq!(min_term_span(&positions), Some(4));
+
+        // a term with no occurrences at all means no window can cover every term
+        let positions = vec![vec![0], vec![]];
+        assert_eq!(min_term_span(&positions), None);
+    }
+
+    #[test]
+    fn test_proximity_score_orders_tighter_spans_higher() {
+        assert!(proximity_score(Some(1)) > proximity_score(Some(5)));
+        assert!(proximity_score(Some(5)) > proximity_score(None));
+    }
+
+    #[test]
+    fn test_rank_by_proximity() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let terms = vec!["lorem".to_string(), "ipsum".to_string()];
+        let uuids = search_uuids_matching_term(&conn, &terms[0])?;
+        let ranked = rank_by_proximity(&conn, uuids, &terms)?;
+
+        // scores must be sorted highest (tightest proximity) first
+        for pair in ranked.windows(2) {
+            assert!(pair[0].1 >= pair[1].1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rank_by_proximity_distance_orders_tightest_first() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let terms = vec!["lorem".to_string(), "ipsum".to_string()];
+        let uuids = search_uuids_matching_term(&conn, &terms[0])?;
+        let ranked = rank_by_proximity_distance(&conn, uuids, &terms)?;
+
+        // distances must be sorted lowest (tightest clustering) first
+        for pair in ranked.windows(2) {
+            assert!(pair[0].1 <= pair[1].1);
+        }
+
+        // a single term always has a distance of zero, since there is no pair to measure
+        let single = vec!["lorem".to_string()];
+        let uuids = search_uuids_matching_term(&conn, &single[0])?;
+        let uuid = *uuids.first().expect("fixture corpus contains 'lorem'");
+        assert_eq!(proximity_distance(&conn, &uuid, &single)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bm25_score_rewards_rarer_terms() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let uuids = search_uuids_matching_term(&conn, &"lorem".to_string())?;
+        let uuid = uuids.first().expect("fixture corpus contains 'lorem'");
+
+        // a document scores non-negative for a term it actually contains
+        let score = bm25_score(&conn, uuid, &["lorem".to_string()])?;
+        assert!(score >= 0.0);
+
+        // a term absent from the corpus contributes nothing
+        let score_absent = bm25_score(&conn, uuid, &["zzznonexistent".to_string()])?;
+        assert_eq!(score_absent, 0.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bm25_score_with_stats_matches_bm25_score() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let uuids = search_uuids_matching_term(&conn, &"lorem".to_string())?;
+        let uuid = uuids.first().expect("fixture corpus contains 'lorem'");
+        let terms = vec!["lorem".to_string()];
+
+        // scoring with precomputed corpus stats must agree with recomputing them inline
+        let stats = corpus_stats(&conn)?;
+        let score = bm25_score(&conn, uuid, &terms)?;
+        let score_with_stats =
+            bm25_score_with_stats(&conn, uuid, &terms, BM25_K1, BM25_B, stats)?;
+        assert_eq!(score, score_with_stats);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_term_frequency_weights_name_field_matches() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let uuid = Uuid::new_v4();
+        conn.execute(
+            "INSERT INTO snip_index_rs(term, uuid, count, positions, field) VALUES ('report', :uuid, 1, '0', 'body')",
+            &[(":uuid", &uuid.to_string())],
+        )?;
+        let body_only = term_frequency(&conn, &uuid, "report")?;
+        assert_eq!(body_only, 1.0);
+
+        conn.execute(
+            "INSERT INTO snip_index_rs(term, uuid, count, positions, field) VALUES ('report', :uuid, 1, '0', 'name')",
+            &[(":uuid", &uuid.to_string())],
+        )?;
+        let body_and_name = term_frequency(&conn, &uuid, "report")?;
+        assert_eq!(body_and_name, 1.0 + NAME_FIELD_BOOST);
 
-    // return only if a singular result is matched, so we check for two results
-    let mut id_str = String::new();
-    for (i, id) in rows.into_iter().enumerate() {
-        if i == 0 {
-            id_str = id.unwrap();
-        } else {
-            return Err(SnipError::UuidMultipleMatches(format!(
-                "provided partial {} returned multiple document uuids",
-                id_partial
-            )));
-        }
+        Ok(())
     }
 
-    if !id_str.is_empty() {
-        return match Uuid::parse_str(&id_str) {
-            Ok(v) => Ok(v),
-            Err(e) => Err(SnipError::General(format!("{}", e))),
-        };
+    #[test]
+    fn test_rank_candidates_orders_by_pipeline() {
+        let uuid_a = Uuid::new_v4();
+        let uuid_b = Uuid::new_v4();
+        let mut candidates = vec![
+            RankedCandidate {
+                uuid: uuid_a,
+                typo_distance: 1,
+                proximity_span: Some(2),
+                exact: false,
+                bm25: 5.0,
+            },
+            RankedCandidate {
+                uuid: uuid_b,
+                typo_distance: 0,
+                proximity_span: Some(10),
+                exact: true,
+                bm25: 1.0,
+            },
+        ];
+        rank_candidates(&mut candidates);
+        // fewer typo corrections outranks a tighter proximity span or higher BM25 score
+        assert_eq!(candidates[0].uuid, uuid_b);
     }
-    Err(SnipError::UuidNotFound(format!(
-        "The document id was not found using id {}",
-        id_partial
-    )))
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::snip;
-    use crate::snip::test_prep::*;
-    use std::collections::HashMap;
-    use std::error::Error;
-    use uuid::Uuid;
+    #[test]
+    fn test_rank_results() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let matched = vec![TolerantMatch {
+            term: "lorem".to_string(),
+            distance: 0,
+        }];
+        let uuids = search_uuids_matching_term(&conn, &matched[0].term)?;
+        let ranked = rank_results(&conn, uuids.clone(), &matched)?;
+
+        assert_eq!(ranked.len(), uuids.len());
+        Ok(())
+    }
 
     #[test]
-    fn test_search_all_present() -> Result<(), Box<dyn Error>> {
+    fn test_sort_by_relevance_ranks_exact_before_fuzzy() -> Result<(), Box<dyn Error>> {
         let conn = prepare_database().expect("preparing in-memory database");
         snip::index_all_items(&conn)?;
 
-        let stemmer = rust_stemmers::Stemmer::create(rust_stemmers::Algorithm::English);
+        let search_query = SearchQuery {
+            terms_include: vec!["lorm".to_string()], // one edit away from the indexed "lorem"
+            terms_exclude: vec![],
+            terms_optional: vec![],
+            method: SearchMethod::IndexFuzzy(1),
+            uuids: vec![],
+            prefix_distance: None,
+        };
+        let mut result = search_structured(&conn, search_query)?;
+        assert!(!result.items.is_empty());
 
-        let terms: Vec<String> = vec![
-            "lorem".to_string(),
-            "ipsum".to_string(),
-            "dolor".to_string(),
-        ];
-        let stems: Vec<String> = terms.iter().map(|w| stemmer.stem(w).to_string()).collect();
-        let result = search_all_present(&conn, stems)?;
+        let search_query = SearchQuery {
+            terms_include: vec!["lorm".to_string()],
+            terms_exclude: vec![],
+            terms_optional: vec![],
+            method: SearchMethod::IndexFuzzy(1),
+            uuids: vec![],
+            prefix_distance: None,
+        };
+        sort_by_relevance(&conn, &mut result, &search_query)?;
 
-        println!("number of results: {}", result.items.len());
-        println!("{:#?}", result);
-        /*
-        for (k, v) in result.items {
-            let s = snip::get_from_uuid(&conn, &k)?;
-            println!("{} {}", s.uuid, s.name);
-            println!("  {:#?}", v);
+        // candidates needing fewer typo corrections (an exact match needs none) rank
+        // first, so the typo distance never decreases down the list; with "lorm" every
+        // candidate here needs one correction
+        assert!(!result.items.is_empty());
+        let distances: Vec<usize> = result
+            .items
+            .iter()
+            .map(|item| search_query_typo_distance(&conn, &item.uuid, &search_query))
+            .collect::<Result<_, _>>()?;
+        for pair in distances.windows(2) {
+            assert!(pair[0] <= pair[1]);
         }
-         */
         Ok(())
     }
 
     #[test]
-    fn test_search_structured() -> Result<(), Box<dyn Error>> {
-        let conn = prepare_database()?;
+    fn test_sort_by_relevance_orders_tighter_proximity_first() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
         snip::index_all_items(&conn)?;
 
-        let query = SearchQuery {
-            // terms_include: vec!["ipsum".to_string(), "dolor".to_string()],
-            terms_include: vec!["in".to_string(), "is".to_string()],
-            terms_exclude: vec!["fuzz".to_string()],
+        let search_query = SearchQuery {
+            terms_include: vec!["lorem".to_string(), "ipsum".to_string()],
+            terms_exclude: vec![],
             terms_optional: vec![],
             method: SearchMethod::IndexStem,
             uuids: vec![],
+            prefix_distance: None,
         };
+        let mut result = search_structured(&conn, search_query)?;
 
-        let expect = SearchQueryResult {
-            items: vec![SearchQueryItem {
-                uuid: Uuid::try_parse("412f7ca8-824c-4c70-80f0-4cca6371e45a")?,
-                score: None,
-                matches: HashMap::from([
-                    (
-                        "in".to_string(),
-                        vec![
-                            116, 159, 352, 730, 794, 809, 1043, 1114, 1143, 1317, 1341, 1362, 1397,
-                            1417,
-                        ],
-                    ),
-                    (
-                        "is".to_string(),
-                        vec![
-                            100, 110, 359, 591, 715, 806, 818, 938, 954, 1023, 1034, 1053, 1171,
-                            1218, 1266, 1370, 1377, 1387, 1393, 1414, 1439, 1512, 1517, 1542, 1591,
-                        ],
-                    ),
-                ]),
-            }],
+        let reference_query = SearchQuery {
+            terms_include: vec!["lorem".to_string(), "ipsum".to_string()],
+            terms_exclude: vec![],
+            terms_optional: vec![],
+            method: SearchMethod::IndexStem,
+            uuids: vec![],
+            prefix_distance: None,
         };
+        sort_by_relevance(&conn, &mut result, &reference_query)?;
 
-        let result = search_structured(&conn, query)?;
-        // println!("expect: {:?}", expect);
-        // println!("result: {:?}", result);
-
-        // verify id, length, and keys only
-        let expect_item = expect.items.first().expect("getting first expect_item");
-        let result_item = result.items.first().expect("getting first result_item");
-        if expect_item.uuid != result_item.uuid {
-            panic!(
-                "expected uuid {} got {}",
-                expect_item.uuid, result_item.uuid
-            );
-        }
-
-        if expect_item.matches != result_item.matches {
-            panic!("expected item {:?} got {:?}", expect_item, result_item);
+        // scores must be sorted tightest-proximity-first with no typo corrections to
+        // break ties on, so span is non-decreasing down the ranked list
+        let spans: Vec<Option<usize>> = result
+            .items
+            .iter()
+            .map(|item| {
+                let positions: Vec<Vec<usize>> = item.matches.values().cloned().collect();
+                min_term_span(&positions)
+            })
+            .collect();
+        for pair in spans.windows(2) {
+            assert!(span_rank(pair[0]) <= span_rank(pair[1]));
         }
-
         Ok(())
     }
 
     #[test]
-    fn test_search_structured_uuids() -> Result<(), Box<dyn Error>> {
+    fn test_score_search_query_populates_score_and_sorts_descending() -> Result<(), Box<dyn Error>> {
         let conn = prepare_database()?;
         snip::index_all_items(&conn)?;
 
-        // Lorem ipsum
-        let id: Uuid = Uuid::try_parse(ID_STR)?;
-        let query = SearchQuery {
-            terms_include: vec!["lorem".to_string(), "ipsum".to_string()],
-            terms_exclude: vec!["fuzz".to_string()],
+        let search_query = SearchQuery {
+            terms_include: vec!["lorem".to_string()],
+            terms_exclude: vec![],
             terms_optional: vec![],
             method: SearchMethod::IndexStem,
-            uuids: vec![id],
+            uuids: vec![],
+            prefix_distance: None,
         };
-        let result = search_structured(&conn, query)?;
-        // println!("result: {:#?}", result);
-        let item = result.items.get(0).unwrap();
-        // check length of positions for "lorem"
-        let item_lorem_len = item.matches.get("lorem").unwrap().len();
-        let item_lorem_len_expect = 2;
-        if item_lorem_len != item_lorem_len_expect {
-            panic!(
-                "expected {} matches for 'lorem', got {}",
-                item_lorem_len_expect, item_lorem_len
-            );
+        // search_structured calls score_search_query internally before returning
+        let result = search_structured(&conn, search_query)?;
+
+        assert!(!result.items.is_empty());
+        for item in &result.items {
+            assert!(item.score.is_some());
         }
-        // check length of positions for "ipsum"
-        let item_ipsum_len = item.matches.get("ipsum").unwrap().len();
-        let item_ipsum_len_expect = 5;
-        if item_ipsum_len != item_ipsum_len_expect {
-            panic!(
-                "expected {} matches for 'ipsum', got {}",
-                item_ipsum_len_expect, item_ipsum_len
-            );
+        for pair in result.items.windows(2) {
+            assert!(pair[0].score.unwrap() >= pair[1].score.unwrap());
         }
 
-        // Fuzzing document
-        let id = Uuid::try_parse("990a917e-66d3-404b-9502-e8341964730b")?;
-        let query = SearchQuery {
-            terms_include: vec!["fuzz".to_string(), "random".to_string()],
-            terms_exclude: vec!["lorem".to_string()],
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_context_memoizes_term_positions() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database()?;
+        snip::index_all_items(&conn)?;
+
+        let ctx = SearchContext::new(&conn);
+        let id = Uuid::try_parse(ID_STR)?;
+        let first = ctx.cached_term_positions(&id, "lorem")?;
+        let second = ctx.cached_term_positions(&id, "lorem")?;
+        assert_eq!(first, second);
+        assert!(!first.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_structured_cached_matches_thin_wrapper() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database()?;
+        snip::index_all_items(&conn)?;
+
+        let search_query = SearchQuery {
+            terms_include: vec!["lorem".to_string(), "ipsum".to_string()],
+            terms_exclude: vec!["fuzz".to_string()],
             terms_optional: vec![],
             method: SearchMethod::IndexStem,
-            uuids: vec![id],
+            uuids: vec![],
+            prefix_distance: None,
         };
-        let result = search_structured(&conn, query)?;
-        // println!("result: {:#?}", result);
-        // check length of positions for "fuzz"
-        let item = result.items.get(0).unwrap();
-        let item_fuzz_len = item.matches.get("fuzz").unwrap().len();
-        let item_fuzz_len_expect = 7;
-        if item_fuzz_len != item_fuzz_len_expect {
-            panic!(
-                "expected {} matches for 'fuzz', got {}",
-                item_fuzz_len_expect, item_fuzz_len
-            );
-        }
-        // check length of positions for "random"
-        let item_random_len = item.matches.get("random").unwrap().len();
-        let item_random_len_expect = 1;
-        if item_random_len != item_random_len_expect {
-            panic!(
-                "expected {} matches for 'random', got {}",
-                item_random_len_expect, item_random_len
-            );
-        }
+
+        let via_wrapper = search_structured(&conn, search_query.clone())?;
+
+        // two successive queries sharing one context exercise the position cache the
+        // way a caller issuing several lookups would
+        let ctx = SearchContext::new(&conn);
+        let _warm = search_structured_cached(&ctx, search_query.clone())?;
+        let via_shared_context = search_structured_cached(&ctx, search_query)?;
+
+        let mut wrapper_uuids: Vec<Uuid> = via_wrapper.items.iter().map(|item| item.uuid).collect();
+        let mut shared_uuids: Vec<Uuid> = via_shared_context.items.iter().map(|item| item.uuid).collect();
+        wrapper_uuids.sort_unstable();
+        shared_uuids.sort_unstable();
+        assert_eq!(wrapper_uuids, shared_uuids);
+        assert!(!wrapper_uuids.is_empty());
 
         Ok(())
     }
 
     #[test]
-    fn test_search_uuid() -> Result<(), Box<dyn Error>> {
+    fn test_query_context_memoizes_term_postings() -> Result<(), Box<dyn Error>> {
         let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
 
-        let id = Uuid::try_parse(ID_STR)?;
-        let partials = fragment_uuid(id);
+        let ctx = QueryContext::new(&conn);
+        let first = ctx.term_postings("lorem")?;
+        let second = ctx.term_postings("lorem")?;
+        assert_eq!(first, second);
 
-        /*
-        println!("ba652e2d-b248-4bcc-b36e-c26c0d0e8002");
-        for p in &partials {
-            println!("{} {}", p.0, p.1);
-        }
-        */
+        Ok(())
+    }
 
-        let expect = match Uuid::parse_str(ID_STR) {
-            Ok(v) => v,
-            Err(e) => panic!("{}", e),
-        };
+    #[test]
+    fn test_query_context_get_word_index_matches_direct_positions() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let ctx = QueryContext::new(&conn);
+        let uuids = search_uuids_matching_term(&conn, &"lorem".to_string())?;
+        let uuid = *uuids.first().expect("fixture corpus contains 'lorem'");
+
+        let index = ctx
+            .get_word_index("lorem", &uuid)?
+            .expect("document known to contain the term");
+        let direct_positions = get_term_positions(&conn, &uuid, &"lorem".to_string())?;
+        let index_positions: Vec<usize> = index.positions.iter().map(|p| *p as usize).collect();
+        assert_eq!(index_positions, direct_positions);
+
+        // a document that doesn't contain the term resolves to None, not an empty index
+        assert!(ctx.get_word_index("zzznonexistent", &uuid)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate_operation_cached_matches_direct() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        let op = parse_query("lorem ipsum");
+        let direct = evaluate_operation(&conn, &op)?;
+
+        let ctx = QueryContext::new(&conn);
+        let cached = evaluate_operation_cached(&ctx, &op)?;
+
+        assert_eq!(direct, cached);
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate_operation_resolves_standalone_not_against_full_corpus() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        // a bare `-term` collapses to a lone `Operation::Not`, which must resolve to
+        // "every document except those matching term" instead of an empty set
+        let op = parse_query("-lorem");
+        let result = evaluate_operation(&conn, &op)?;
+        assert!(!result.is_empty());
+        assert!(!result.contains(&Uuid::try_parse(ID_STR)?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate_operation_and_of_all_negated_terms_resolves_against_full_corpus() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        // every operand negated (no positive leaf at all) must fall back to the full
+        // corpus minus the negated terms, not an empty set
+        let op = Operation::And(vec![Operation::Not(Box::new(Operation::Query(
+            "lorem".to_string(),
+        )))]);
+        let result = evaluate_operation(&conn, &op)?;
+        assert!(!result.is_empty());
+        assert!(!result.contains(&Uuid::try_parse(ID_STR)?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_synonym_expands_query_postings() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        // an `Operation::Query` leaf (as opposed to the trailing `Prefix` leaf
+        // `parse_query` would produce for a single bare word) matches nothing on its own;
+        // its text is stemmed the way `parse_query` and `add_synonym` stem it
+        let op = Operation::Query(stem_term("zzznonexistent"));
+        assert!(evaluate_operation(&conn, &op)?.is_empty());
+
+        // once registered as a synonym of "lorem", it resolves to "lorem"'s postings
+        add_synonym(&conn, "zzznonexistent", "lorem")?;
+        let expanded = evaluate_operation(&conn, &op)?;
+        let direct = evaluate_operation(&conn, &Operation::Query("lorem".to_string()))?;
+        assert_eq!(expanded, direct);
+        assert!(!expanded.is_empty());
+
+        // removing it drops the expansion again
+        remove_synonym(&conn, "zzznonexistent", "lorem")?;
+        assert!(evaluate_operation(&conn, &op)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_synonym_bidirectional() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        add_synonym_bidirectional(&conn, "lorem", "zzznonexistent")?;
+        let from_a = evaluate_operation(&conn, &Operation::Query(stem_term("zzznonexistent")))?;
+        let from_b = evaluate_operation(&conn, &Operation::Query("lorem".to_string()))?;
+        assert_eq!(from_a, from_b);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tolerant_terms() -> Result<(), Box<dyn Error>> {
+        let conn = prepare_database().expect("preparing in-memory database");
+        snip::index_all_items(&conn)?;
+
+        // "lorm" is a one-edit typo of "lorem", which should appear in the indexed corpus
+        let matches = tolerant_terms(&conn, "lorm")?;
+        assert!(matches.iter().any(|m| m.term == "lorem" && m.distance == 1));
 
-        // test all uuid string partials
-        for p in &partials {
-            println!("search uuid string: {}", p.0);
-            let id = search_uuid(&conn, p.0);
-            match id {
-                Ok(v) => assert_eq!(expect, v),
-                Err(e) => panic!("{}, full: {}, partial: {}", e, ID_STR, &p.0),
-            }
-        }
         Ok(())
     }
 }